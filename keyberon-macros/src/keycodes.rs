@@ -2,7 +2,7 @@ use quote::quote;
 use proc_macro_error::emit_error;
 use proc_macro2::*;
 
-pub fn punctuation_to_keycode(p: &Punct, out: &mut TokenStream) {
+pub fn punctuation_to_keycode(p: &Punct, raw: bool, out: &mut TokenStream) {
     match p.as_char() {
         // Normal punctuation
         '-' => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Minus), }),
@@ -13,28 +13,28 @@ pub fn punctuation_to_keycode(p: &Punct, out: &mut TokenStream) {
         '/' => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Slash), }),
 
         // Shifted punctuation
-        '!' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb1]), }),
-        '@' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb2]), }),
-        '#' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb3]), }),
-        '$' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb4]), }),
-        '%' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb5]), }),
-        '^' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb6]), }),
-        '&' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb7]), }),
-        '*' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb8]), }),
-        '_' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Minus]), }),
-        '+' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Equal]), }),
-        '|' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Bslash]), }),
-        '~' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Grave]), }),
-        '<' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Comma]), }),
-        '>' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Dot]), }),
-        '?' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Slash]), }),
-        ':' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::SColon]), }),
+        '!' => shifted("Kb1", raw, out),
+        '@' => shifted("Kb2", raw, out),
+        '#' => shifted("Kb3", raw, out),
+        '$' => shifted("Kb4", raw, out),
+        '%' => shifted("Kb5", raw, out),
+        '^' => shifted("Kb6", raw, out),
+        '&' => shifted("Kb7", raw, out),
+        '*' => shifted("Kb8", raw, out),
+        '_' => shifted("Minus", raw, out),
+        '+' => shifted("Equal", raw, out),
+        '|' => shifted("Bslash", raw, out),
+        '~' => shifted("Grave", raw, out),
+        '<' => shifted("Comma", raw, out),
+        '>' => shifted("Dot", raw, out),
+        '?' => shifted("Slash", raw, out),
+        ':' => shifted("SColon", raw, out),
         // Is this reachable?
         _ => emit_error!(p, "Punctuation could not be parsed as a keycode")
     }
 }
 
-pub fn literal_to_keycode(l: &Literal, out: &mut TokenStream) {
+pub fn literal_to_keycode(l: &Literal, raw: bool, out: &mut TokenStream) {
     //let repr = l.to_string();
     match l.to_string().as_str() {
         "1" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb1), }),
@@ -55,12 +55,12 @@ pub fn literal_to_keycode(l: &Literal, out: &mut TokenStream) {
         "'['" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::LBracket), }),
         "']'" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::RBracket), }),
         "'`'" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Grave), }),
-        "'\"'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Quote]), }),
-        "'('" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb9]), }),
-        "')'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb0]), }),
-        "'{'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::LBracket]), }),
-        "'}'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::RBracket]), }),
-        "'_'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Minus]), }),
+        "'\"'" => shifted("Quote", raw, out),
+        "'('" => shifted("Kb9", raw, out),
+        "')'" => shifted("Kb0", raw, out),
+        "'{'" => shifted("LBracket", raw, out),
+        "'}'" => shifted("RBracket", raw, out),
+        "'_'" => shifted("Minus", raw, out),
 
         s if s.starts_with('\'') => emit_error!(l, "Literal could not be parsed as a keycode"; help = "Maybe try without quotes?"),
 
@@ -73,4 +73,15 @@ pub fn literal_to_keycode(l: &Literal, out: &mut TokenStream) {
         }
         _ => emit_error!(l, "Literal could not be parsed as a keycode")
     }
-}
\ No newline at end of file
+}
+
+/// Emits a shifted keycode: `Action::MultipleKeyCodes(&[LShift, <keycode>])`,
+/// or just the keycode in raw mode.
+fn shifted(keycode: &str, raw: bool, out: &mut TokenStream) {
+    let keycode = Ident::new(keycode, Span::call_site());
+    if raw {
+        out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::#keycode), });
+    } else {
+        out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::#keycode]), });
+    }
+}
@@ -14,10 +14,35 @@ pub fn layout(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     (quote! { [#parsed] }).into()
 }
 
+/// Declares a static (or a const) holding the layers given with the
+/// `layout!` syntax, its `Layers` type being inferred from them:
+///
+/// ```ignore
+/// layout_with_dims! {
+///     pub static LAYERS: NoCustom = {
+///         [A B C]
+///         [D E F]
+///     }
+///     {
+///         [1 2 3]
+///         [t t t]
+///     }
+/// }
+/// ```
+///
+/// declares `pub static LAYERS: Layers<NoCustom, 3, 2, 2>`. All the
+/// layers must have the same number of rows, and all the rows the
+/// same number of keys.
+#[proc_macro_error]
+#[proc_macro]
+pub fn layout_with_dims(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    parse_layout_with_dims(input.into()).into()
+}
+
 #[proc_macro_error]
 #[proc_macro]
 pub fn layer(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let parsed = parse_layer(input.into());
+    let parsed = parse_layer(input.into(), false);
 
     (quote! { [#parsed] }).into()
 }
@@ -25,7 +50,7 @@ pub fn layer(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 #[proc_macro_error]
 #[proc_macro]
 pub fn row(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let parsed = parse_row(input.into());
+    let parsed = parse_row(input.into(), false);
 
     (quote! { [#parsed] }).into()
 }
\ No newline at end of file
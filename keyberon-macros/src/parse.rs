@@ -1,5 +1,5 @@
 extern crate proc_macro;
-use proc_macro2::{Delimiter, Group, Punct, Spacing, TokenStream, TokenTree};
+use proc_macro2::{Delimiter, Group, Ident, Punct, Spacing, Span, TokenStream, TokenTree};
 use proc_macro_error::{abort, emit_error};
 use quote::quote;
 
@@ -7,11 +7,22 @@ use crate::keycodes::*;
 
 pub fn parse_layout(input: TokenStream) -> TokenStream {
     let mut out = TokenStream::new();
+    let mut tokens: Vec<_> = input.into_iter().collect();
 
-    for t in input {
+    // A leading `#raw` directive: the shifted characters are not
+    // wrapped with `LShift`, for the hosts not using a US layout
+    let raw = match tokens.as_slice() {
+        [TokenTree::Punct(p), TokenTree::Ident(i), ..] if p.as_char() == '#' && i == "raw" => {
+            tokens.drain(..2);
+            true
+        }
+        _ => false,
+    };
+
+    for t in tokens {
         match t {
             TokenTree::Group(g) if g.delimiter() == Delimiter::Brace => {
-                let layer = parse_layer(g.stream());
+                let layer = parse_layer(g.stream(), raw);
                 out.extend(quote! {
                     [#layer],
                 });
@@ -23,13 +34,73 @@ pub fn parse_layout(input: TokenStream) -> TokenStream {
     out
 }
 
-pub fn parse_layer(input: TokenStream) -> TokenStream {
+/// Parses the input of `layout_with_dims!`: `<item> NAME: T = <layers>`,
+/// returning the declaration of the item with its `Layers` type.
+pub fn parse_layout_with_dims(input: TokenStream) -> TokenStream {
+    let mut tokens = input.into_iter();
+    let item: TokenStream = tokens
+        .by_ref()
+        .take_while(|t| !matches!(t, TokenTree::Punct(p) if p.as_char() == ':'))
+        .collect();
+    let custom: TokenStream = tokens
+        .by_ref()
+        .take_while(|t| !matches!(t, TokenTree::Punct(p) if p.as_char() == '='))
+        .collect();
+    let layers: TokenStream = tokens.collect();
+    if item.is_empty() || custom.is_empty() || layers.is_empty() {
+        abort!(
+            Span::call_site(),
+            "Expected a declaration: static NAME: CustomType = {{ ... }}"
+        );
+    }
+
+    // The dimensions of the first layer and of its first row, checked
+    // on the others
+    let mut dims = None;
+    let mut nb_layers = 0usize;
+    for t in layers.clone() {
+        if let TokenTree::Group(layer) = t {
+            nb_layers += 1;
+            let rows: Vec<_> = layer
+                .stream()
+                .into_iter()
+                .filter_map(|r| match r {
+                    TokenTree::Group(g) if g.delimiter() == Delimiter::Bracket => Some(g),
+                    _ => None,
+                })
+                .collect();
+            match dims {
+                Some((_, nb_rows)) if nb_rows != rows.len() => {
+                    emit_error!(layer, "Expected {} rows in this layer", nb_rows)
+                }
+                _ => (),
+            }
+            for row in rows.iter() {
+                let nb_cols = row.stream().into_iter().count();
+                match dims {
+                    None => dims = Some((nb_cols, rows.len())),
+                    Some((cols, _)) if cols != nb_cols => {
+                        emit_error!(row, "Expected {} keys in this row", cols)
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    let (cols, rows) = dims.unwrap_or((0, 0));
+    let parsed = parse_layout(layers);
+    quote! {
+        #item: keyberon::layout::Layers<#custom, #cols, #rows, #nb_layers> = [#parsed];
+    }
+}
+
+pub fn parse_layer(input: TokenStream, raw: bool) -> TokenStream {
     let mut out = TokenStream::new();
 
     for t in input {
         match t {
             TokenTree::Group(g) if g.delimiter() == Delimiter::Bracket => {
-                let row = parse_row(g.stream());
+                let row = parse_row(g.stream(), raw);
                 out.extend(quote! {
                     [#row],
                 });
@@ -42,7 +113,7 @@ pub fn parse_layer(input: TokenStream) -> TokenStream {
     out
 }
 
-pub fn parse_row(input: TokenStream) -> TokenStream {
+pub fn parse_row(input: TokenStream, raw: bool) -> TokenStream {
     let mut out = TokenStream::new();
 
     for t in input {
@@ -54,16 +125,16 @@ pub fn parse_row(input: TokenStream) -> TokenStream {
                     keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::#i),
                 }),
             },
-            TokenTree::Punct(p) => punctuation_to_keycode(&p, &mut out),
-            TokenTree::Literal(l) => literal_to_keycode(&l, &mut out),
-            TokenTree::Group(g) => parse_group(&g, &mut out),
+            TokenTree::Punct(p) => punctuation_to_keycode(&p, raw, &mut out),
+            TokenTree::Literal(l) => literal_to_keycode(&l, raw, &mut out),
+            TokenTree::Group(g) => parse_group(&g, raw, &mut out),
         }
     }
 
     out
 }
 
-pub fn parse_group(g: &Group, out: &mut TokenStream) {
+pub fn parse_group(g: &Group, raw: bool, out: &mut TokenStream) {
     match g.delimiter() {
         // Handle empty groups
         Delimiter::Parenthesis if g.stream().is_empty() => {
@@ -81,28 +152,146 @@ pub fn parse_group(g: &Group, out: &mut TokenStream) {
             let tokens = g.stream();
             out.extend(quote! { keyberon::action::Action::Layer(#tokens), });
         }
-        // Pass the expression unchanged (adding a comma after it)
-        Delimiter::Brace => out.extend(g.stream().into_iter().chain(TokenStream::from(
-            TokenTree::Punct(Punct::new(',', Spacing::Alone)),
-        ))),
-        // Multiple keycodes (Action::MultipleKeyCodes)
-        Delimiter::Bracket => parse_keycode_group(g.stream(), out),
+        Delimiter::Brace => {
+            let stream = g.stream();
+            if let Some(keycodes) = with_mods(stream.clone()) {
+                // Keycode with modifiers (Action::MultipleKeyCodes)
+                out.extend(multiple_keycodes(&keycodes));
+            } else if let Some((hold, tap)) = mod_tap(stream.clone()) {
+                // Mod-tap (Action::HoldTap)
+                out.extend(quote! {
+                    keyberon::action::mt(
+                        &keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::#hold),
+                        &keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::#tap),
+                    ),
+                });
+            } else {
+                // Pass the expression unchanged (adding a comma after it)
+                out.extend(stream.into_iter().chain(TokenStream::from(TokenTree::Punct(
+                    Punct::new(',', Spacing::Alone),
+                ))));
+            }
+        }
+        Delimiter::Bracket => match nested_keycodes(g.stream()) {
+            // Keycodes only, nested groups flattened (Action::MultipleKeyCodes)
+            Some(keycodes) => out.extend(multiple_keycodes(&keycodes)),
+            // Multiple actions (Action::MultipleActions)
+            None => parse_keycode_group(g.stream(), raw, out),
+        },
 
         // Is this reachable?
         Delimiter::None => emit_error!(g, "Unexpected group"),
     }
 }
 
-pub fn parse_keycode_group(input: TokenStream, out: &mut TokenStream) {
+/// Returns the `Action::MultipleKeyCodes` pressing the keycodes.
+fn multiple_keycodes(keycodes: &[Ident]) -> TokenStream {
+    quote! {
+        keyberon::action::Action::MultipleKeyCodes(
+            &[#(keyberon::key_code::KeyCode::#keycodes),*]
+        ),
+    }
+}
+
+/// Parses a keycode with modifiers, such as `ctrl(C)` or
+/// `ctrl(shift(C))`, returning the modifiers followed by the keycode.
+pub fn with_mods(input: TokenStream) -> Option<Vec<Ident>> {
+    let mut tokens = input.into_iter();
+    let (f, g) = match (tokens.next(), tokens.next(), tokens.next()) {
+        (Some(TokenTree::Ident(f)), Some(TokenTree::Group(g)), None)
+            if g.delimiter() == Delimiter::Parenthesis =>
+        {
+            (f, g)
+        }
+        _ => return None,
+    };
+    let modifier = match f.to_string().as_str() {
+        "ctrl" => "LCtrl",
+        "shift" => "LShift",
+        "alt" => "LAlt",
+        "gui" => "LGui",
+        _ => return None,
+    };
+    let mut keycodes = vec![Ident::new(modifier, f.span())];
+    let mut inner = g.stream().into_iter();
+    match (inner.next(), inner.next()) {
+        (Some(TokenTree::Ident(i)), None) => keycodes.push(i),
+        _ => keycodes.extend(with_mods(g.stream())?),
+    }
+    Some(keycodes)
+}
+
+/// Parses a mod-tap, such as `mt(LCtrl, A)`, returning the hold and
+/// the tap keycodes.
+pub fn mod_tap(input: TokenStream) -> Option<(Ident, Ident)> {
+    let mut tokens = input.into_iter();
+    let g = match (tokens.next(), tokens.next(), tokens.next()) {
+        (Some(TokenTree::Ident(f)), Some(TokenTree::Group(g)), None)
+            if f == "mt" && g.delimiter() == Delimiter::Parenthesis =>
+        {
+            g
+        }
+        _ => return None,
+    };
+    let mut inner = g.stream().into_iter();
+    match (inner.next(), inner.next(), inner.next(), inner.next()) {
+        (
+            Some(TokenTree::Ident(hold)),
+            Some(TokenTree::Punct(p)),
+            Some(TokenTree::Ident(tap)),
+            None,
+        ) if p.as_char() == ',' => Some((hold, tap)),
+        _ => None,
+    }
+}
+
+/// Parses a group of keycodes only nesting other groups, such as
+/// `[[LCtrl A] B]`, returning the keycodes flattened. A flat group,
+/// such as `[LCtrl A]`, is left to `parse_keycode_group`, keeping its
+/// `Action::MultipleActions`.
+fn nested_keycodes(input: TokenStream) -> Option<Vec<Ident>> {
+    let nested = input.clone().into_iter().any(|t| match t {
+        TokenTree::Group(g) => g.delimiter() == Delimiter::Bracket,
+        _ => false,
+    });
+    if nested {
+        pure_keycodes(input)
+    } else {
+        None
+    }
+}
+
+/// Parses a group of keycodes only, such as `[LCtrl A]` or
+/// `[[LCtrl A] B]`, returning the keycodes with the nested groups
+/// flattened.
+pub fn pure_keycodes(input: TokenStream) -> Option<Vec<Ident>> {
+    let mut keycodes = vec![];
+    for t in input {
+        match t {
+            TokenTree::Ident(i) => keycodes.push(i),
+            TokenTree::Group(g) if g.delimiter() == Delimiter::Bracket => {
+                keycodes.extend(pure_keycodes(g.stream())?)
+            }
+            _ => return None,
+        }
+    }
+    if keycodes.is_empty() {
+        None
+    } else {
+        Some(keycodes)
+    }
+}
+
+pub fn parse_keycode_group(input: TokenStream, raw: bool, out: &mut TokenStream) {
     let mut inner = TokenStream::new();
     for t in input {
         match t {
             TokenTree::Ident(i) => inner.extend(quote! {
                 keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::#i),
             }),
-            TokenTree::Punct(p) => punctuation_to_keycode(&p, &mut inner),
-            TokenTree::Literal(l) => literal_to_keycode(&l, &mut inner),
-            TokenTree::Group(g) => parse_group(&g, &mut inner),
+            TokenTree::Punct(p) => punctuation_to_keycode(&p, raw, &mut inner),
+            TokenTree::Literal(l) => literal_to_keycode(&l, raw, &mut inner),
+            TokenTree::Group(g) => parse_group(&g, raw, &mut inner),
         }
     }
     out.extend(quote! { keyberon::action::Action::MultipleActions(&[#inner]), });
@@ -1,8 +1,8 @@
 extern crate keyberon_macros;
-use keyberon::action::{k, l, m, Action, Action::*, HoldTapConfig};
+use keyberon::action::{k, l, m, mt, Action, Action::*, HoldTapConfig};
 use keyberon::key_code::KeyCode::*;
 use keyberon::layout::*;
-use keyberon_macros::layout;
+use keyberon_macros::{layout, layout_with_dims};
 
 #[test]
 fn test_layout_equality() {
@@ -18,6 +18,7 @@ fn test_layout_equality() {
         tap: &Action::KeyCode(Enter),
         config: HoldTapConfig::PermissiveHold,
         tap_hold_interval: 0,
+        on_timeout: None,
     };
 
     #[rustfmt::skip]
@@ -70,6 +71,60 @@ fn test_nesting() {
     assert_eq!(A, B);
 }
 
+#[test]
+fn test_flattening() {
+    static NESTED: Layers<NoCustom, 3, 1, 1> = layout! {
+        {
+            [[[LCtrl A] B] [LCtrl A B] [[LCtrl A] (1)]]
+        }
+    };
+    static FLAT: Layers<NoCustom, 3, 1, 1> = [[[
+        m(&[LCtrl, A, B]),
+        Action::MultipleActions(&[k(LCtrl), k(A), k(B)]),
+        Action::MultipleActions(&[Action::MultipleActions(&[k(LCtrl), k(A)]), Action::Layer(1)]),
+    ]]];
+    assert_eq!(NESTED, FLAT);
+}
+
+#[test]
+fn test_international_keys() {
+    static ISO: Layers<NoCustom, 4, 1, 1> = layout! {
+        {
+            [NonUsBslash NonUsHash Intl1 Lang1]
+        }
+    };
+    static EXPECTED: Layers<NoCustom, 4, 1, 1> =
+        [[[k(NonUsBslash), k(NonUsHash), k(Intl1), k(Lang1)]]];
+    assert_eq!(ISO, EXPECTED);
+    assert_eq!(ISO[0][0][0].key_codes().next(), Some(NonUsBslash));
+    assert_eq!(0x64, NonUsBslash as u8);
+}
+
+#[test]
+fn test_layout_with_dims() {
+    layout_with_dims! {
+        static WITH_DIMS: NoCustom = {
+            [A B C]
+            [D E F]
+        }
+        {
+            [1 2 3]
+            [t [LCtrl C] t]
+        }
+    }
+    static EXPECTED: Layers<NoCustom, 3, 2, 2> = layout! {
+        {
+            [A B C]
+            [D E F]
+        }
+        {
+            [1 2 3]
+            [t [LCtrl C] t]
+        }
+    };
+    assert_eq!(WITH_DIMS, EXPECTED);
+}
+
 #[test]
 fn test_layer_switch() {
     static A: Layers<NoCustom, 5, 1, 1> = layout! {
@@ -97,3 +152,67 @@ fn test_escapes() {
     static B: Layers<NoCustom, 2, 1, 1> = [[[k(Bslash), k(Quote)]]];
     assert_eq!(A, B);
 }
+
+#[test]
+fn test_raw() {
+    static RAW: Layers<NoCustom, 5, 1, 1> = layout! {
+        #raw
+        {
+            [! ? '(' [LCtrl @] 1]
+        }
+    };
+    static EXPECTED: Layers<NoCustom, 5, 1, 1> = [[[
+        k(Kb1),
+        k(Slash),
+        k(Kb9),
+        MultipleActions(&[k(LCtrl), k(Kb2)]),
+        k(Kb1),
+    ]]];
+    assert_eq!(RAW, EXPECTED);
+
+    // shifted without the directive
+    static SHIFTED: Layers<NoCustom, 1, 1, 1> = layout! {
+        {
+            [!]
+        }
+    };
+    assert_eq!(SHIFTED, [[[m(&[LShift, Kb1])]]]);
+}
+
+#[test]
+fn test_with_mods() {
+    static A: Layers<NoCustom, 4, 1, 1> = layout! {
+        {
+            [{ctrl(C)} {ctrl(shift(Tab))} {gui(alt(ctrl(Delete)))} {Action::NoOp}]
+        }
+    };
+    static B: Layers<NoCustom, 4, 1, 1> = [[[
+        m(&[LCtrl, C]),
+        m(&[LCtrl, LShift, Tab]),
+        m(&[LGui, LAlt, LCtrl, Delete]),
+        NoOp,
+    ]]];
+    assert_eq!(A, B);
+}
+
+#[test]
+fn test_mod_tap() {
+    static A: Layers<NoCustom, 3, 1, 1> = layout! {
+        {
+            [{mt(LCtrl, Z)} {mt(RAlt, Enter)} {mt(&k(LCtrl), &k(Z))}]
+        }
+    };
+    static B: Layers<NoCustom, 3, 1, 1> = [[[
+        Action::HoldTap {
+            timeout: 200,
+            hold: &k(LCtrl),
+            tap: &k(Z),
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+            on_timeout: None,
+        },
+        mt(&k(RAlt), &k(Enter)),
+        mt(&k(LCtrl), &k(Z)),
+    ]]];
+    assert_eq!(A, B);
+}
@@ -24,9 +24,46 @@ pub enum HoldTapConfig {
     /// events than on timing. Be aware that doing the good succession
     /// of key might require some training.
     PermissiveHold,
+    /// Like `Default`, only the timeout will determine between hold
+    /// and tap action, but the tap action is strongly preferred: the
+    /// hold action is activated only if the timeout elapsed while the
+    /// key is held. Even when too many events are waiting for the
+    /// resolution (which forces the other configurations into the
+    /// hold action), the tap action is activated.
+    ///
+    /// This behavior is interesting for a key where an accidental
+    /// hold is annoying. It is usually used with a longer timeout.
+    TapPreferred,
+    /// Chordal hold: if a key of the opposite hand is pressed, the
+    /// hold action is activated, and if a key of the same hand is
+    /// pressed, the tap action is activated.
+    ///
+    /// The hand of the keys is given by `Layout::set_hand_map`.
+    /// Without it, all the keys are considered on the opposite hand,
+    /// as `HoldOnOtherKeyPress`. This behavior is interesting for the
+    /// home row mods: a same hand roll is typed as taps.
+    ChordalHold,
+}
+
+/// A step of an `Action::Macro`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MacroStep {
+    /// Presses the key code.
+    Press(KeyCode),
+    /// Releases the key code.
+    Release(KeyCode),
+    /// Presses the key code, and releases it at the next tick.
+    Tap(KeyCode),
 }
 
 /// The different actions that can be done.
+///
+/// Actions are compared by value: the `&'static` fields (such as the
+/// `hold` and `tap` actions of a `HoldTap`, or the slices of
+/// `MultipleKeyCodes` and `MultipleActions`) compare the referenced
+/// values, not the addresses. Thus, two identical actions defined in
+/// different statics are equal, allowing to deduplicate them.
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Action<T = core::convert::Infallible>
@@ -43,22 +80,73 @@ where
     /// Multiple key codes sent at the same time, as if these keys
     /// were pressed at the same time. Useful to send a shifted key,
     /// or complex shortcuts like Ctrl+Alt+Del in a single key press.
+    ///
+    /// All the key codes are attached to the key, and are released
+    /// together with it: a key has a single press and a single
+    /// release, there is no event to release them independently. To
+    /// give each key code its own lifetime, use `MultipleActions` with
+    /// actions managing their lifetime, such as `OneShot` or
+    /// `LatchKeyCode`.
     MultipleKeyCodes(&'static [KeyCode]),
     /// Multiple actions sent at the same time.
+    ///
+    /// A `HoldTap` (or `TapDanceHold`) is performed after the other
+    /// actions, and waits for its resolution as usual. Only one of
+    /// them can wait at a time: the next ones are ignored.
     MultipleActions(&'static [Action<T>]),
     /// While pressed, change the current layer. That's the classic
     /// Fn key. If several layer actions are active at the same time,
     /// their numbers are summed. For example, if you press at the same
-    /// time `Layer(1)` and `Layer(2)`, layer 3 will be active.
+    /// time `Layer(1)` and `Layer(2)`, layer 3 will be active. Several
+    /// keys activating the same layer are counted only once: pressing
+    /// two `Layer(1)` keys activates layer 1, until both are released.
     Layer(usize),
+    /// While pressed, change the current layer to `layer`, as
+    /// `Layer`. While this layer is active, its `Trans` keys perform
+    /// the action of the `fallback` layer instead of the default
+    /// layer. A `Trans` key of the `fallback` layer then performs the
+    /// action of the default layer.
+    LayerWithFallback {
+        /// The layer activated while pressed.
+        layer: usize,
+        /// The layer used by the `Trans` keys of `layer`.
+        fallback: usize,
+    },
+    /// Momentary layer with a sticky tap: while pressed, activates
+    /// the given layer, as `Layer`. But if the key is released less
+    /// than `timeout` ticks after its press, and no other key was
+    /// pressed meanwhile, the layer stays active for the next key
+    /// press only, as a one shot layer.
+    StickyLayer {
+        /// The layer activated.
+        layer: usize,
+        /// The maximum duration, in ticks, of a tap arming the one
+        /// shot layer.
+        timeout: u16,
+    },
+    /// While pressed, activates the layer at the given offset from
+    /// the current layer, clamped to the existing layers. For
+    /// example, `LayerRelative(-1)` goes back one layer.
+    ///
+    /// The layer is computed at press time, and then behaves as a
+    /// `Layer` action: as such, it is summed with the other active
+    /// layer actions (see `Layout::current_layer`).
+    LayerRelative(i8),
     /// Change the default layer.
     DefaultLayer(usize),
     /// If the key is held more than `timeout` ticks (usually
     /// milliseconds), performs the `hold` action, else performs the
     /// `tap` action.  Mostly used with a modifier for the hold action
     /// and a normal key on the tap action. Any action can be
-    /// performed, but using a `HoldTap` in a `HoldTap` is not
-    /// specified (but guaranteed to not crash).
+    /// performed.
+    ///
+    /// A `HoldTap` as the `hold` action is chained: once the outer
+    /// hold-tap is resolved as a hold, the inner one waits for its
+    /// own resolution, starting at this time. For example, with two
+    /// timeouts of 100 ticks, a key held for 150 ticks performs the
+    /// inner tap action, and a key held for more than 200 ticks the
+    /// inner hold action. A `HoldTap` as the `tap` action is
+    /// performed as its tap action, as the key is already released.
     ///
     /// Different behaviors can be configured using the config field,
     /// but whatever the configuration is, if the key is pressed more
@@ -88,6 +176,116 @@ where
         /// Not implemented yet, to not have behavior change with an
         /// update, set this to 0.
         tap_hold_interval: u16,
+        /// The action performed instead of `hold` when the hold is
+        /// caused by the timeout, i.e. the key was held alone for
+        /// `timeout` ticks. `None` to always perform `hold`.
+        on_timeout: Option<&'static Action<T>>,
+    },
+    /// Performs the `tap` action on a tap, the `double` action on a
+    /// double tap, and the `hold` action if the key is held more than
+    /// `timeout` ticks (usually milliseconds).
+    ///
+    /// The second tap must be pressed less than `timeout` ticks after
+    /// the first press. Thus, the tap action is delayed by `timeout`
+    /// ticks. A classic example is a thumb key performing `Space` on
+    /// tap, changing the default layer on double tap, and activating
+    /// a layer while held.
+    ///
+    /// The taps are not counted: the double tap ends the tap dance,
+    /// and a third tap starts a new one. Thus, mashing the key
+    /// performs `double` for each pair of taps, and `tap` for a last
+    /// odd tap, without any state growing with the number of taps.
+    TapDanceHold {
+        /// The duration, in ticks (usually milliseconds) giving the
+        /// difference between a hold and a tap, and the maximum
+        /// delay to start the double tap.
+        timeout: u16,
+        /// The tap action.
+        tap: &'static Action<T>,
+        /// The hold action.
+        hold: &'static Action<T>,
+        /// The double tap action.
+        double: &'static Action<T>,
+    },
+    /// Adaptive key: performs the action of the first rule matching
+    /// the last non modifier key code pressed, or the `default`
+    /// action if no rule matches.
+    ///
+    /// For example, `Adaptive { default: &k(O), rules: &[(A, &k(U))] }`
+    /// performs `U` if pressed after `A`, and `O` otherwise.
+    Adaptive {
+        /// The action performed if no rule matches.
+        default: &'static Action<T>,
+        /// The rules, as pairs of previous key code and action.
+        rules: &'static [(KeyCode, &'static Action<T>)],
+    },
+    /// Repeat key: performs the last non modifier key code pressed,
+    /// with the modifiers that were active at the time. For example,
+    /// after `Ctrl+Z`, it performs `Ctrl+Z` again.
+    Repeat,
+    /// A macro: the steps are played one per tick, independently of
+    /// the physical release of the key. The key codes still pressed
+    /// at the end of the macro are released automatically.
+    ///
+    /// Pressing another macro key while a macro is playing stops the
+    /// running macro.
+    Macro(&'static [MacroStep]),
+    /// One shot key code, usually a modifier.
+    ///
+    /// When pressed, the key code is active until the next non
+    /// modifier key code is released, even if the one shot key is
+    /// released before. Several one shot keys can be armed at the
+    /// same time: for example, tapping a one shot `LShift` and then a
+    /// one shot `LCtrl` before pressing `A` will send `Ctrl+Shift+A`.
+    /// Pressing an armed one shot key again cancels it.
+    OneShot(KeyCode),
+    /// Latched key code: a press activates the key code until the
+    /// next press of the same key code, the releases being ignored.
+    /// Useful for a "hold W" key in games, or a sticky modifier.
+    LatchKeyCode(KeyCode),
+    /// Hold for momentary, double tap to lock: a press performs the
+    /// `inner` action until the release, but a double tap (a press
+    /// less than `timeout` ticks after the previous release) keeps
+    /// it active after the release. Another double tap releases it.
+    ///
+    /// Useful for a layer or a modifier (`&l(1)` or `&k(LShift)` for
+    /// example).
+    DoubleTapLock {
+        /// The action performed.
+        inner: &'static Action<T>,
+        /// The maximum delay, in ticks, between the release and the
+        /// next press of a double tap.
+        timeout: u16,
+    },
+    /// A weak key code: as `KeyCode`, but when building a report with
+    /// `Layout::hid_report`, it is only sent if a slot of the report
+    /// is left by the other key codes, instead of causing a rollover
+    /// error. Useful for a key whose loss is harmless when many keys
+    /// are pressed.
+    WeakKeyCode(KeyCode),
+    /// A key code repeated by the keyboard instead of the host: while
+    /// the key is held, the key code is pressed, and after
+    /// `initial_delay` ticks it is tapped every `interval` ticks
+    /// (released for a tick, then pressed again). Useful for a scroll
+    /// key, with a rate independent of the host autorepeat.
+    RepeatKey {
+        /// The repeated key code.
+        key: KeyCode,
+        /// The delay, in ticks, before the first repetition.
+        initial_delay: u16,
+        /// The period, in ticks, of the repetitions (at least 2).
+        interval: u16,
+    },
+    /// Locks the layout: everything is released, and then all the
+    /// events are ignored until the keys of `unlock_sequence` are
+    /// pressed in order (see `Layout::is_locked`). Any other press
+    /// restarts the sequence, the last keys pressed counting as its
+    /// start if they match it (`a a b` is unlocked by `a a a b`).
+    /// Does nothing if the sequence is empty.
+    Lock {
+        /// The coordinates of the keys to press to unlock the
+        /// layout.
+        unlock_sequence: &'static [(u8, u8)],
     },
     /// Custom action.
     ///
@@ -96,6 +294,37 @@ where
     /// to drive any non keyboard related actions that you might
     /// manage with key events.
     Custom(T),
+    /// Custom action released automatically after `ticks` ticks
+    /// (usually milliseconds), independently of the physical
+    /// release of the key.
+    ///
+    /// Useful to drive a pulse (for example triggering a relay) that
+    /// must not depend on how long the key is held.
+    CustomPulse {
+        /// The user defined action, as in `Custom`.
+        value: T,
+        /// The duration of the pulse, in ticks.
+        ticks: u16,
+    },
+    /// Custom action or key code, chosen at press time by the
+    /// predicate given to `Layout::set_custom_predicate`: the custom
+    /// action `value`, as a `Custom` action, if it returns `true`,
+    /// else the key code `key`. Without a predicate, the custom
+    /// action is performed.
+    CustomOrKey {
+        /// The user defined action, as in `Custom`.
+        value: T,
+        /// The key code performed instead of the custom action.
+        key: KeyCode,
+    },
+    /// Custom action cycling through the given values: each press
+    /// activates the next value (wrapping to the first one after the
+    /// last one), as a `Custom` action would.
+    ///
+    /// Useful to step through modes, for example the effects of the
+    /// RGB underglow. The position in the cycle is tracked by the
+    /// `Layout` for each key.
+    CustomCycle(&'static [&'static T]),
 }
 impl<T> Action<T> {
     /// Gets the layer number if the action is the `Layer` action.
@@ -108,11 +337,53 @@ impl<T> Action<T> {
     /// Returns an iterator on the `KeyCode` corresponding to the action.
     pub fn key_codes(&self) -> impl Iterator<Item = KeyCode> + '_ {
         match self {
-            Action::KeyCode(kc) => core::slice::from_ref(kc).iter().cloned(),
+            Action::KeyCode(kc) | Action::WeakKeyCode(kc) | Action::RepeatKey { key: kc, .. } => {
+                core::slice::from_ref(kc).iter().cloned()
+            }
             Action::MultipleKeyCodes(kcs) => kcs.iter().cloned(),
             _ => [].iter().cloned(),
         }
     }
+    /// Returns `true` if the action is `NoOp`.
+    pub fn is_noop(&self) -> bool {
+        matches!(self, Action::NoOp)
+    }
+    /// Returns `true` if the action is `Trans`.
+    pub fn is_trans(&self) -> bool {
+        matches!(self, Action::Trans)
+    }
+    /// Returns `true` if the action is the `KeyCode` action.
+    pub fn is_keycode(&self) -> bool {
+        matches!(self, Action::KeyCode(_))
+    }
+    /// Gets the key code if the action is the `KeyCode` action.
+    pub fn as_keycode(&self) -> Option<KeyCode> {
+        match self {
+            Action::KeyCode(kc) => Some(*kc),
+            _ => None,
+        }
+    }
+    /// Returns `true` if the action is the `Layer` action.
+    pub fn is_layer(&self) -> bool {
+        matches!(self, Action::Layer(_))
+    }
+    /// Returns `true` if the action is a `HoldTap` or a
+    /// `TapDanceHold`, i.e. an action resolved after its press.
+    pub fn is_hold_tap(&self) -> bool {
+        matches!(self, Action::HoldTap { .. } | Action::TapDanceHold { .. })
+    }
+    /// Gets the user defined value if the action is `Custom`,
+    /// `CustomPulse` or `CustomOrKey`. A `CustomCycle` gives `None`:
+    /// the value it performs depends on the position of its key in
+    /// the cycle, known by the `Layout` only.
+    pub fn as_custom(&self) -> Option<&T> {
+        match self {
+            Action::Custom(value)
+            | Action::CustomPulse { value, .. }
+            | Action::CustomOrKey { value, .. } => Some(value),
+            _ => None,
+        }
+    }
 }
 
 /// A shortcut to create a `Action::KeyCode`, useful to create compact
@@ -134,7 +405,109 @@ pub const fn d<T>(layer: usize) -> Action<T> {
 }
 
 /// A shortcut to create a `Action::MultipleKeyCodes`, useful to
-/// create compact layout.
+/// create compact layout. A key code with forced modifiers, as
+/// `Ctrl+C`, is `m(&[LCtrl, C])`.
 pub const fn m<T>(kcs: &'static [KeyCode]) -> Action<T> {
     Action::MultipleKeyCodes(kcs)
 }
+
+/// A shortcut to create a mod-tap, i.e. a `Action::HoldTap` with the
+/// default configuration and a timeout of 200 ticks, useful to
+/// create compact layout. A home row `LCtrl` on `A` is
+/// `mt(&k(LCtrl), &k(A))`.
+pub const fn mt<T>(hold: &'static Action<T>, tap: &'static Action<T>) -> Action<T> {
+    Action::HoldTap {
+        timeout: 200,
+        hold,
+        tap,
+        config: HoldTapConfig::Default,
+        tap_hold_interval: 0,
+        on_timeout: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::key_code::KeyCode::*;
+
+    #[test]
+    fn equality() {
+        static HOLD1: Action = k(LCtrl);
+        static HOLD2: Action = k(LCtrl);
+        static TAP: Action = k(Space);
+        let hold_tap = |hold, timeout| Action::HoldTap {
+            timeout,
+            hold,
+            tap: &TAP,
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+            on_timeout: None,
+        };
+
+        // references are compared by value
+        assert_eq!(hold_tap(&HOLD1, 200), hold_tap(&HOLD2, 200));
+        assert_ne!(hold_tap(&HOLD1, 200), hold_tap(&HOLD1, 180));
+        assert_ne!(hold_tap(&HOLD1, 200), hold_tap(&TAP, 200));
+        static KCS: [KeyCode; 2] = [LShift, A];
+        assert_eq!(m::<()>(&KCS), m(&[LShift, A]));
+        assert_ne!(m::<()>(&KCS), m(&[A, LShift]));
+        static MT: Action = mt(&k(LCtrl), &k(Space));
+        assert_eq!(hold_tap(&HOLD1, 200), MT);
+        static ACTIONS: [Action; 2] = [k(A), l(1)];
+        assert_eq!(
+            Action::MultipleActions(&ACTIONS),
+            Action::MultipleActions(&[Action::KeyCode(A), Action::Layer(1)])
+        );
+        assert_ne!(
+            Action::MultipleActions(&ACTIONS),
+            Action::MultipleActions(&[Action::KeyCode(A), Action::DefaultLayer(1)])
+        );
+
+        // custom actions are compared with their own equality
+        assert_eq!(Action::Custom(42), Action::Custom(42));
+        assert_ne!(Action::Custom(42), Action::Custom(43));
+        assert_ne!(Action::Custom(42), Action::NoOp);
+    }
+
+    #[test]
+    fn predicates() {
+        static SPACE: Action<u8> = k(Space);
+        let hold_tap = Action::HoldTap {
+            timeout: 200,
+            hold: &Action::Layer(1),
+            tap: &SPACE,
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+            on_timeout: None,
+        };
+        assert!(hold_tap.is_hold_tap());
+        assert!(!hold_tap.is_keycode());
+        assert_eq!(None, hold_tap.as_keycode());
+
+        assert!(SPACE.is_keycode());
+        assert_eq!(Some(Space), SPACE.as_keycode());
+        assert!(!SPACE.is_layer());
+        assert_eq!(None, m::<u8>(&[LShift, A]).as_keycode());
+
+        assert!(l::<u8>(1).is_layer());
+        assert!(!d::<u8>(1).is_layer());
+        assert!(Action::<u8>::NoOp.is_noop());
+        assert!(Action::<u8>::Trans.is_trans());
+        assert!(!Action::<u8>::Trans.is_noop());
+
+        assert_eq!(Some(&42), Action::Custom(42).as_custom());
+        let pulse = Action::CustomPulse {
+            value: 43,
+            ticks: 10,
+        };
+        assert_eq!(Some(&43), pulse.as_custom());
+        let or_key = Action::CustomOrKey {
+            value: 44,
+            key: KeyCode::A,
+        };
+        assert_eq!(Some(&44), or_key.as_custom());
+        assert_eq!(None, Action::CustomCycle(&[&45, &46]).as_custom());
+        assert_eq!(None, SPACE.as_custom());
+    }
+}
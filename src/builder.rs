@@ -0,0 +1,174 @@
+//! Building layers at runtime, for example from a layout editor.
+//!
+//! The layers are assembled from slices, and then leaked to get the
+//! `&'static` layers needed by `Layout`. Needs the `alloc` feature.
+//!
+//! ```
+//! use keyberon::action::{k, l, Action};
+//! use keyberon::builder::LayoutBuilder;
+//! use keyberon::key_code::KeyCode::*;
+//! use keyberon::layout::{Layout, NoCustom};
+//!
+//! let layers = LayoutBuilder::<NoCustom>::new()
+//!     .layer(&[&[k(A), l(1)]])
+//!     .layer(&[&[k(B), Action::Trans]])
+//!     .build::<2, 1, 2>()
+//!     .unwrap();
+//! let layout = Layout::new(layers);
+//! ```
+
+use crate::action::Action;
+use crate::layout::Layers;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// The error returned when the layers don't have the expected
+/// dimensions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// Wrong number of layers.
+    Layers {
+        /// The expected number of layers.
+        expected: usize,
+        /// The number of layers of the builder.
+        found: usize,
+    },
+    /// Wrong number of rows in a layer.
+    Rows {
+        /// The index of the layer.
+        layer: usize,
+        /// The expected number of rows.
+        expected: usize,
+        /// The number of rows of the layer.
+        found: usize,
+    },
+    /// Wrong number of columns in a row.
+    Columns {
+        /// The index of the layer.
+        layer: usize,
+        /// The index of the row.
+        row: usize,
+        /// The expected number of columns.
+        expected: usize,
+        /// The number of columns of the row.
+        found: usize,
+    },
+}
+
+/// A builder of layers from slices of actions.
+#[derive(Debug)]
+pub struct LayoutBuilder<T: 'static> {
+    layers: Vec<Vec<Vec<Action<T>>>>,
+}
+
+impl<T: 'static> Default for LayoutBuilder<T> {
+    fn default() -> Self {
+        Self { layers: Vec::new() }
+    }
+}
+
+impl<T: Clone + 'static> LayoutBuilder<T> {
+    /// Creates a builder without any layer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer, given as a slice of rows.
+    pub fn layer(&mut self, rows: &[&[Action<T>]]) -> &mut Self {
+        self.layers.push(rows.iter().map(|r| r.to_vec()).collect());
+        self
+    }
+
+    /// Checks the dimensions of the layers, and returns them. The
+    /// layers are leaked: they live until the end of the program.
+    pub fn build<const C: usize, const R: usize, const L: usize>(
+        &self,
+    ) -> Result<&'static Layers<T, C, R, L>, BuildError> {
+        if self.layers.len() != L {
+            return Err(BuildError::Layers {
+                expected: L,
+                found: self.layers.len(),
+            });
+        }
+        let mut layers = Vec::with_capacity(L);
+        for (i, layer) in self.layers.iter().enumerate() {
+            if layer.len() != R {
+                return Err(BuildError::Rows {
+                    layer: i,
+                    expected: R,
+                    found: layer.len(),
+                });
+            }
+            let mut rows = Vec::with_capacity(R);
+            for (j, row) in layer.iter().enumerate() {
+                let row =
+                    <[Action<T>; C]>::try_from(row.clone()).map_err(|r| BuildError::Columns {
+                        layer: i,
+                        row: j,
+                        expected: C,
+                        found: r.len(),
+                    })?;
+                rows.push(row);
+            }
+            layers.push(<[[Action<T>; C]; R]>::try_from(rows).unwrap_or_else(|_| unreachable!()));
+        }
+        let layers = <Layers<T, C, R, L>>::try_from(layers).unwrap_or_else(|_| unreachable!());
+        Ok(Box::leak(Box::new(layers)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::action::{k, l};
+    use crate::key_code::KeyCode::*;
+    use crate::layout::{Event, Layout, NoCustom};
+
+    #[test]
+    fn build() {
+        let layers = LayoutBuilder::<NoCustom>::new()
+            .layer(&[&[k(A), l(1)], &[k(B), k(C)]])
+            .layer(&[&[k(Kb1), Action::Trans], &[Action::Trans, k(Kb2)]])
+            .build::<2, 2, 2>()
+            .unwrap();
+        let mut layout = Layout::new(layers);
+        layout.event(Event::Press(0, 1));
+        layout.tick();
+        layout.event(Event::Press(1, 0));
+        layout.event(Event::Press(1, 1));
+        layout.tick();
+        layout.tick();
+        assert!(layout.keycodes().eq([B, Kb2].iter().copied()));
+    }
+
+    #[test]
+    fn dimensions() {
+        let mut builder = LayoutBuilder::<NoCustom>::new();
+        builder.layer(&[&[k(A), k(B)], &[k(C)]]);
+        assert_eq!(
+            Err(BuildError::Layers {
+                expected: 2,
+                found: 1
+            }),
+            builder.build::<2, 2, 2>()
+        );
+        assert_eq!(
+            Err(BuildError::Rows {
+                layer: 0,
+                expected: 3,
+                found: 2
+            }),
+            builder.build::<2, 3, 1>()
+        );
+        assert_eq!(
+            Err(BuildError::Columns {
+                layer: 0,
+                row: 1,
+                expected: 2,
+                found: 1
+            }),
+            builder.build::<2, 2, 1>()
+        );
+    }
+}
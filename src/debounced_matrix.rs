@@ -22,15 +22,70 @@ impl StateTracker for () {
     fn emit_event(&self, _: &Self::State, _: &Self::State) -> Option<Event> { None }
 }
 
-pub struct DebouncedMatrix<C, R, T, const CS: usize, const RS: usize, const B: u32>
+/// An electrically unlikely scan, such as a whole row changing at
+/// once (usually a short).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anomaly {
+    /// The number of keys that changed during the scan.
+    pub changed: u32,
+}
+
+/// An error of a pin of a `DebouncedMatrix`, a `ShiftRegMatrix` or a
+/// `CharlieMatrix`, with the operation that failed and the index of
+/// the pin. Useful to find a bad pin during the bring-up of a board.
+///
+/// For a `CharlieMatrix`, the row is the driven pin and the column
+/// the pin read. Deselecting a pin switches it back to an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixError<E> {
+    /// Deselecting a row (setting it high) at initialization failed.
+    Clear {
+        /// The index of the row.
+        row: usize,
+        /// The error of the pin.
+        error: E,
+    },
+    /// Selecting a row (setting it low) to scan it failed.
+    SelectRow {
+        /// The index of the row.
+        row: usize,
+        /// The error of the pin.
+        error: E,
+    },
+    /// Reading a column failed.
+    ReadColumn {
+        /// The index of the column.
+        col: usize,
+        /// The error of the pin.
+        error: E,
+    },
+    /// Deselecting a row (setting it high) after its scan failed.
+    DeselectRow {
+        /// The index of the row.
+        row: usize,
+        /// The error of the pin.
+        error: E,
+    },
+}
+
+/// The progress of a cooperative scan, see `DebouncedMatrix::scan_step`.
+#[derive(Debug)]
+pub enum ScanProgress<I> {
+    /// Some rows remain to be scanned.
+    InProgress,
+    /// All the rows are scanned, with the debounced events if any
+    /// (as returned by `scan`).
+    Complete(Option<I>),
+}
+
+/// The debouncing logic shared by the matrix implementations, and
+/// its settings, reached through the `debounce` and `debounce_mut`
+/// methods of the matrices. Each row is stored as a bitfield of the
+/// pressed columns.
+pub struct Debounce<T, const RS: usize, const B: u32>
 where
-    C: InputPin,
-    R: OutputPin,
     T: StateTracker,
 {
-    cols: [C; CS],
-    rows: [R; RS],
-
     // Last known good state
     current: [u32; RS],
     // State currently being debounced
@@ -39,57 +94,36 @@ where
     tracked: T,
     last_tracked: T::State,
     last_stable_tracked: T::State,
+    max_changes: Option<u32>,
+    anomaly: Option<Anomaly>,
+    releases_first: bool,
 }
 
-impl<C, R, T, E, const CS: usize, const RS: usize, const B: u32> DebouncedMatrix<C, R, T, CS, RS, B>
+impl<T, const RS: usize, const B: u32> Debounce<T, RS, B>
 where
-    C: InputPin<Error = E>,
-    R: OutputPin<Error = E>,
     T: StateTracker,
 {
-    pub fn new(cols: [C; CS], rows: [R; RS], tracked: T) -> Result<Self, E>
-    where
-        C: InputPin<Error = E>,
-        R: OutputPin<Error = E>,
-    {
-        let mut res = Self {
-            cols,
-            rows,
+    fn new(tracked: T) -> Self {
+        Self {
             current: [0; RS],
             new: [0; RS],
             since: 0,
             last_tracked: tracked.default_state(),
             last_stable_tracked: tracked.default_state(),
             tracked,
-        };
-        res.clear()?;
-        Ok(res)
-    }
-
-    fn clear(&mut self) -> Result<(), E> {
-        for r in self.rows.iter_mut() {
-            r.set_high()?;
+            max_changes: None,
+            anomaly: None,
+            releases_first: false,
         }
-        Ok(())
     }
 
-    fn update(&mut self) -> Result<bool, E> {
-        let mut pressed_now = [0; RS];
-        for (ri, row) in (&mut self.rows).iter_mut().enumerate() {
-            row.set_low()?;
-            for (ci, col) in (&self.cols).iter().enumerate() {
-                if col.is_low()? {
-                    pressed_now[ri] |= 1 << ci;
-                }
-            }
-            row.set_high()?;
-        }
-
+    fn update(&mut self, pressed_now: [u32; RS]) -> bool {
         let tracked_now = self.tracked.get_state();
+        self.anomaly = None;
 
         if pressed_now == self.current && tracked_now == self.last_stable_tracked {
             self.since = 0;
-            return Ok(false);
+            return false;
         }
         if self.new != pressed_now || self.last_tracked != tracked_now {
             self.new = pressed_now;
@@ -100,33 +134,963 @@ where
         }
 
         if self.since > B {
+            let changed = self
+                .current
+                .iter()
+                .zip(self.new.iter())
+                .map(|(c, n)| (c ^ n).count_ones())
+                .sum();
+            if matches!(self.max_changes, Some(max) if changed > max) {
+                self.anomaly = Some(Anomaly { changed });
+                self.since = 0;
+                return false;
+            }
             core::mem::swap(&mut self.current, &mut self.new);
             core::mem::swap(&mut self.last_stable_tracked, &mut self.last_tracked);
             self.since = 0;
-            Ok(true)
+            true
         } else {
-            Ok(false)
+            false
+        }
+    }
+
+    /// Forgets the change being debounced: the next update starts
+    /// the debouncing from the last known good state.
+    fn prime(&mut self) {
+        self.new = self.current;
+        self.last_tracked = self.last_stable_tracked;
+        self.since = 0;
+        self.anomaly = None;
+    }
+
+    /// Updates the debouncing with a scan, returning the events of
+    /// the validated changes, if any.
+    fn scan(&mut self, pressed_now: [u32; RS]) -> Option<impl Iterator<Item = Event> + '_> {
+        if self.update(pressed_now) {
+            Some(self.events())
+        } else {
+            None
+        }
+    }
+
+    /// Sets the maximum number of keys that can change in a single
+    /// debounced scan. If more keys change, the scan is considered
+    /// as an anomaly (see `last_scan_anomaly`) and its events are
+    /// suppressed. Disabled (`None`) by default.
+    pub fn set_max_changes(&mut self, max: Option<u32>) {
+        self.max_changes = max;
+    }
+
+    /// Returns the anomaly detected by the last scan, if any.
+    pub fn last_scan_anomaly(&self) -> Option<Anomaly> {
+        self.anomaly
+    }
+
+    /// If `true`, all the `Release` events of a scan are emitted
+    /// before its `Press` events. Else (the default), the events are
+    /// emitted in the order of the keys.
+    pub fn set_releases_first(&mut self, releases_first: bool) {
+        self.releases_first = releases_first;
+    }
+
+    fn events(&self) -> impl Iterator<Item = Event> + '_ {
+        // `None` for all the events, `Some(is_press)` to filter them
+        let passes: &[Option<bool>] = if self.releases_first {
+            &[Some(false), Some(true)]
+        } else {
+            &[None]
+        };
+        passes
+            .iter()
+            .flat_map(move |&pass| {
+                self.changes().filter(move |e| match pass {
+                    Some(is_press) => e.is_press() == is_press,
+                    None => true,
+                })
+            })
+            .chain(self.tracked.emit_event(&self.last_tracked, &self.last_stable_tracked))
+    }
+
+    fn changes(&self) -> impl Iterator<Item = Event> + '_ {
+        self.new
+            .iter()
+            .zip(self.current.iter())
+            .enumerate()
+            .flat_map(move |(i, (o, n))| {
+                (0..u32::BITS).filter_map(move |b| match (o & (1 << b), n & (1 << b)) {
+                    (0, 1..=u32::MAX) => Some(Event::Press(i as u8, b as u8)),
+                    (1..=u32::MAX, 0) => Some(Event::Release(i as u8, b as u8)),
+                    _ => None,
+                })
+            })
+    }
+}
+
+/// The debouncing of the matrices, driven by raw samples instead of
+/// pins. Useful for a custom scanning, or to test the debouncing
+/// with synthetic samples.
+///
+/// Each sample is an array of rows, with the bitfield of the
+/// pressed columns of each row (bit `j` set if the key at column `j`
+/// is pressed). A change is validated when the same sample is
+/// given for more than `B` consecutive updates.
+pub struct MatrixDebouncer<const RS: usize, const B: u32> {
+    debounce: Debounce<(), RS, B>,
+}
+
+impl<const RS: usize, const B: u32> Default for MatrixDebouncer<RS, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const RS: usize, const B: u32> MatrixDebouncer<RS, B> {
+    /// Creates a debouncer, with all the keys released.
+    pub fn new() -> Self {
+        Self {
+            debounce: Debounce::new(()),
+        }
+    }
+
+    /// The debouncing of the samples.
+    pub fn debounce(&self) -> &Debounce<(), RS, B> {
+        &self.debounce
+    }
+
+    /// The debouncing of the samples, to change its settings.
+    pub fn debounce_mut(&mut self) -> &mut Debounce<(), RS, B> {
+        &mut self.debounce
+    }
+
+    /// Updates the debouncer with a raw sample. Returns the events
+    /// of the validated changes, if any.
+    pub fn update(&mut self, raw: &[u32; RS]) -> Option<impl Iterator<Item = Event> + '_> {
+        self.debounce.scan(*raw)
+    }
+}
+
+pub struct DebouncedMatrix<C, R, T, const CS: usize, const RS: usize, const B: u32>
+where
+    C: InputPin,
+    R: OutputPin,
+    T: StateTracker,
+{
+    cols: [C; CS],
+    rows: [R; RS],
+    debounce: Debounce<T, RS, B>,
+    // State of the cooperative scan
+    next_row: usize,
+    pressed_now: [u32; RS],
+}
+
+impl<C, R, T, E, const CS: usize, const RS: usize, const B: u32> DebouncedMatrix<C, R, T, CS, RS, B>
+where
+    C: InputPin<Error = E>,
+    R: OutputPin<Error = E>,
+    T: StateTracker,
+{
+    pub fn new(cols: [C; CS], rows: [R; RS], tracked: T) -> Result<Self, MatrixError<E>>
+    where
+        C: InputPin<Error = E>,
+        R: OutputPin<Error = E>,
+    {
+        let mut res = Self {
+            cols,
+            rows,
+            debounce: Debounce::new(tracked),
+            next_row: 0,
+            pressed_now: [0; RS],
+        };
+        res.clear()?;
+        Ok(res)
+    }
+
+    fn clear(&mut self) -> Result<(), MatrixError<E>> {
+        for (row, r) in self.rows.iter_mut().enumerate() {
+            r.set_high()
+                .map_err(|error| MatrixError::Clear { row, error })?;
+        }
+        Ok(())
+    }
+
+    fn scan_row(&mut self, ri: usize) -> Result<u32, MatrixError<E>> {
+        let mut pressed = 0;
+        self.rows[ri]
+            .set_low()
+            .map_err(|error| MatrixError::SelectRow { row: ri, error })?;
+        for (ci, col) in (&self.cols).iter().enumerate() {
+            let low = col
+                .is_low()
+                .map_err(|error| MatrixError::ReadColumn { col: ci, error })?;
+            if low {
+                pressed |= 1 << ci;
+            }
         }
+        self.rows[ri]
+            .set_high()
+            .map_err(|error| MatrixError::DeselectRow { row: ri, error })?;
+        Ok(pressed)
+    }
+
+    fn read(&mut self) -> Result<[u32; RS], MatrixError<E>> {
+        let mut pressed_now = [0; RS];
+        for (ri, pressed) in pressed_now.iter_mut().enumerate() {
+            *pressed = self.scan_row(ri)?;
+        }
+
+        Ok(pressed_now)
+    }
+
+    /// The debouncing of the matrix.
+    pub fn debounce(&self) -> &Debounce<T, RS, B> {
+        &self.debounce
+    }
+
+    /// The debouncing of the matrix, to change its settings.
+    pub fn debounce_mut(&mut self) -> &mut Debounce<T, RS, B> {
+        &mut self.debounce
+    }
+
+    /// Forces the next scan to re-evaluate the matrix from the last
+    /// debounced state, forgetting the change being debounced and
+    /// restarting a cooperative scan. To be called after waking up on
+    /// a pin change interrupt: the debouncing progress made before
+    /// the sleep is stale, and a transient that has already settled
+    /// must not be validated by it.
+    pub fn prime(&mut self) {
+        self.debounce.prime();
+        self.next_row = 0;
+        self.pressed_now = [0; RS];
+    }
+
+    pub fn scan(&mut self) -> Result<Option<impl Iterator<Item = Event> + '_>, MatrixError<E>> {
+        let pressed_now = self.read()?;
+        Ok(self.debounce.scan(pressed_now))
+    }
+
+    /// Scans a single row, allowing to interleave a long scan with
+    /// other tasks. Each call scans the next row, and the call
+    /// scanning the last row returns `ScanProgress::Complete` with
+    /// the result of the whole scan, as `scan` would have returned.
+    ///
+    /// A cooperative scan must not be interleaved with calls to
+    /// `scan`. Without any row, each call is a complete scan.
+    pub fn scan_step(
+        &mut self,
+    ) -> Result<ScanProgress<impl Iterator<Item = Event> + '_>, MatrixError<E>> {
+        if RS == 0 {
+            return Ok(ScanProgress::Complete(self.debounce.scan([0; RS])));
+        }
+        let ri = self.next_row;
+        self.pressed_now[ri] = self.scan_row(ri)?;
+        self.next_row += 1;
+        if self.next_row < RS {
+            return Ok(ScanProgress::InProgress);
+        }
+
+        self.next_row = 0;
+        let pressed_now = core::mem::replace(&mut self.pressed_now, [0; RS]);
+        Ok(ScanProgress::Complete(self.debounce.scan(pressed_now)))
+    }
+}
+
+/// A debounced matrix driven by closures instead of pins.
+///
+/// Useful when the matrix is behind an I/O expander (for example an
+/// MCP23017 on I2C): rather than toggling and reading each pin
+/// individually, `set_rows` and `read_cols` can each be a single bus
+/// transaction.
+///
+/// `set_rows` is called with the bitfield of the row to scan (bit
+/// `i` for row `i`), and with `0` once the scan is done. `read_cols`
+/// must then return the bitfield of the pressed columns (bit `j` set
+/// if the key at column `j` is pressed). `RS` must thus be at most
+/// 32, which is checked at compile time.
+///
+/// The errors of the closures are returned as is, not as a
+/// `MatrixError`: each closure drives all the rows or reads all the
+/// columns at once, so there is no pin to report.
+pub struct ClosureMatrix<S, Rd, T, const RS: usize, const B: u32>
+where
+    T: StateTracker,
+{
+    set_rows: S,
+    read_cols: Rd,
+    debounce: Debounce<T, RS, B>,
+}
+
+impl<S, Rd, T, E, const RS: usize, const B: u32> ClosureMatrix<S, Rd, T, RS, B>
+where
+    S: FnMut(u32) -> Result<(), E>,
+    Rd: FnMut() -> Result<u32, E>,
+    T: StateTracker,
+{
+    const ROWS_FIT: () = assert!(RS <= 32, "a ClosureMatrix has at most 32 rows");
+
+    pub fn new(mut set_rows: S, read_cols: Rd, tracked: T) -> Result<Self, E> {
+        let () = Self::ROWS_FIT;
+        set_rows(0)?;
+        Ok(Self {
+            set_rows,
+            read_cols,
+            debounce: Debounce::new(tracked),
+        })
+    }
+
+    fn read(&mut self) -> Result<[u32; RS], E> {
+        let mut pressed_now = [0; RS];
+        for (ri, pressed) in pressed_now.iter_mut().enumerate() {
+            (self.set_rows)(1 << ri)?;
+            *pressed = (self.read_cols)()?;
+        }
+        (self.set_rows)(0)?;
+
+        Ok(pressed_now)
+    }
+
+    /// The debouncing of the matrix.
+    pub fn debounce(&self) -> &Debounce<T, RS, B> {
+        &self.debounce
+    }
+
+    /// The debouncing of the matrix, to change its settings.
+    pub fn debounce_mut(&mut self) -> &mut Debounce<T, RS, B> {
+        &mut self.debounce
     }
 
     pub fn scan(&mut self) -> Result<Option<impl Iterator<Item = Event> + '_>, E> {
-        if self.update()? {
-            let iter = 
-                self.new
+        let pressed_now = self.read()?;
+        Ok(self.debounce.scan(pressed_now))
+    }
+}
+
+/// A debounced matrix whose columns are read through a shift
+/// register (for example a 74HC165 on SPI) instead of one pin per
+/// column.
+///
+/// Each row pin is in turn driven low, as for `DebouncedMatrix`, and
+/// `read_cols` must then clock in the columns and return their
+/// bitfield (bit `j` set if the key at column `j` is pressed).
+pub struct ShiftRegMatrix<R, Rd, T, const RS: usize, const B: u32>
+where
+    R: OutputPin,
+    T: StateTracker,
+{
+    rows: [R; RS],
+    read_cols: Rd,
+    debounce: Debounce<T, RS, B>,
+}
+
+impl<R, Rd, T, E, const RS: usize, const B: u32> ShiftRegMatrix<R, Rd, T, RS, B>
+where
+    R: OutputPin<Error = E>,
+    Rd: FnMut() -> u32,
+    T: StateTracker,
+{
+    pub fn new(rows: [R; RS], read_cols: Rd, tracked: T) -> Result<Self, MatrixError<E>> {
+        let mut res = Self {
+            rows,
+            read_cols,
+            debounce: Debounce::new(tracked),
+        };
+        res.clear()?;
+        Ok(res)
+    }
+
+    fn clear(&mut self) -> Result<(), MatrixError<E>> {
+        for (row, r) in self.rows.iter_mut().enumerate() {
+            r.set_high()
+                .map_err(|error| MatrixError::Clear { row, error })?;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<[u32; RS], MatrixError<E>> {
+        let mut pressed_now = [0; RS];
+        for (row, pressed) in pressed_now.iter_mut().enumerate() {
+            self.rows[row]
+                .set_low()
+                .map_err(|error| MatrixError::SelectRow { row, error })?;
+            *pressed = (self.read_cols)();
+            self.rows[row]
+                .set_high()
+                .map_err(|error| MatrixError::DeselectRow { row, error })?;
+        }
+
+        Ok(pressed_now)
+    }
+
+    /// The debouncing of the matrix.
+    pub fn debounce(&self) -> &Debounce<T, RS, B> {
+        &self.debounce
+    }
+
+    /// The debouncing of the matrix, to change its settings.
+    pub fn debounce_mut(&mut self) -> &mut Debounce<T, RS, B> {
+        &mut self.debounce
+    }
+
+    pub fn scan(&mut self) -> Result<Option<impl Iterator<Item = Event> + '_>, MatrixError<E>> {
+        let pressed_now = self.read()?;
+        Ok(self.debounce.scan(pressed_now))
+    }
+}
+
+/// A pin that can be switched between a low output and a high
+/// impedance input, as needed by the charlieplexed matrices.
+pub trait TriStatePin {
+    type Error;
+    /// Switches the pin to an output driven low.
+    fn set_output_low(&mut self) -> Result<(), Self::Error>;
+    /// Switches the pin to a high impedance input, with a pull-up.
+    fn set_input(&mut self) -> Result<(), Self::Error>;
+    /// Returns `true` if the pin, as an input, reads low.
+    fn is_low(&self) -> Result<bool, Self::Error>;
+}
+
+/// A debounced charlieplexed matrix of `N` pins, driving up to
+/// `N * (N - 1)` keys.
+///
+/// Each pin is in turn driven low while the others are high
+/// impedance inputs: the key `(i, j)` is pressed if pin `j` reads
+/// low while pin `i` is driven. The keys must have a diode from pin
+/// `j` to pin `i`. `N` must be at most 32, which is checked at
+/// compile time.
+pub struct CharlieMatrix<P, T, const N: usize, const B: u32>
+where
+    P: TriStatePin,
+    T: StateTracker,
+{
+    pins: [P; N],
+    debounce: Debounce<T, N, B>,
+}
+
+impl<P, T, E, const N: usize, const B: u32> CharlieMatrix<P, T, N, B>
+where
+    P: TriStatePin<Error = E>,
+    T: StateTracker,
+{
+    const PINS_FIT: () = assert!(N <= 32, "a CharlieMatrix has at most 32 pins");
+
+    pub fn new(pins: [P; N], tracked: T) -> Result<Self, MatrixError<E>> {
+        let () = Self::PINS_FIT;
+        let mut res = Self {
+            pins,
+            debounce: Debounce::new(tracked),
+        };
+        res.clear()?;
+        Ok(res)
+    }
+
+    fn clear(&mut self) -> Result<(), MatrixError<E>> {
+        for (row, p) in self.pins.iter_mut().enumerate() {
+            p.set_input()
+                .map_err(|error| MatrixError::Clear { row, error })?;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<[u32; N], MatrixError<E>> {
+        let mut pressed_now = [0; N];
+        for (row, pressed) in pressed_now.iter_mut().enumerate() {
+            self.pins[row]
+                .set_output_low()
+                .map_err(|error| MatrixError::SelectRow { row, error })?;
+            for (col, pin) in self.pins.iter().enumerate() {
+                if col == row {
+                    continue;
+                }
+                let low = pin
+                    .is_low()
+                    .map_err(|error| MatrixError::ReadColumn { col, error })?;
+                if low {
+                    *pressed |= 1 << col;
+                }
+            }
+            self.pins[row]
+                .set_input()
+                .map_err(|error| MatrixError::DeselectRow { row, error })?;
+        }
+
+        Ok(pressed_now)
+    }
+
+    /// The debouncing of the matrix.
+    pub fn debounce(&self) -> &Debounce<T, N, B> {
+        &self.debounce
+    }
+
+    /// The debouncing of the matrix, to change its settings.
+    pub fn debounce_mut(&mut self) -> &mut Debounce<T, N, B> {
+        &mut self.debounce
+    }
+
+    pub fn scan(&mut self) -> Result<Option<impl Iterator<Item = Event> + '_>, MatrixError<E>> {
+        let pressed_now = self.read()?;
+        Ok(self.debounce.scan(pressed_now))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    #[test]
+    fn matrix_debouncer() {
+        let mut debouncer = MatrixDebouncer::<2, 2>::new();
+        assert!(debouncer.update(&[0, 0]).is_none());
+
+        // bouncing: never stable long enough
+        for _ in 0..5 {
+            assert!(debouncer.update(&[0, 1 << 3]).is_none());
+            assert!(debouncer.update(&[0, 1 << 3]).is_none());
+            assert!(debouncer.update(&[0, 0]).is_none());
+        }
+
+        // settled after 3 identical samples
+        assert!(debouncer.update(&[1, 1 << 3]).is_none());
+        assert!(debouncer.update(&[1, 1 << 3]).is_none());
+        let mut events = debouncer.update(&[1, 1 << 3]).unwrap();
+        assert_eq!(Some(Event::Press(0, 0)), events.next());
+        assert_eq!(Some(Event::Press(1, 3)), events.next());
+        assert_eq!(None, events.next());
+        drop(events);
+        assert!(debouncer.update(&[1, 1 << 3]).is_none());
+
+        // a release
+        assert!(debouncer.update(&[1, 0]).is_none());
+        assert!(debouncer.update(&[1, 0]).is_none());
+        let mut events = debouncer.update(&[1, 0]).unwrap();
+        assert_eq!(Some(Event::Release(1, 3)), events.next());
+        assert_eq!(None, events.next());
+    }
+
+    #[test]
+    fn closure_matrix() {
+        let pressed = [Cell::new(0u32), Cell::new(0u32)];
+        let active_row = Cell::new(0u32);
+        let mut matrix = ClosureMatrix::<_, _, (), 2, 1>::new(
+            |mask| {
+                active_row.set(mask);
+                Ok::<(), Infallible>(())
+            },
+            || {
+                let mask = active_row.get();
+                Ok(pressed
                     .iter()
-                    .zip(self.current.iter())
                     .enumerate()
-                    .flat_map(move |(i, (o, n))| {
-                        (0..u32::BITS).filter_map(move |b| match (o & (1 << b), n & (1 << b)) {
-                            (0, 1..=u32::MAX) => Some(Event::Press(i as u8, b as u8)),
-                            (1..=u32::MAX, 0) => Some(Event::Release(i as u8, b as u8)),
-                            _ => None,
-                        })
-                    })
-                    .chain(self.tracked.emit_event(&self.last_tracked, &self.last_stable_tracked));
-            Ok(Some(iter))
-        } else {
-            Ok(None)
+                    .filter(|(i, _)| mask & 1 << i != 0)
+                    .fold(0, |cols, (_, r)| cols | r.get()))
+            },
+            (),
+        )
+        .unwrap();
+
+        assert!(matrix.scan().unwrap().is_none());
+
+        // press (1, 2), debounced after 2 scans
+        pressed[1].set(1 << 2);
+        assert!(matrix.scan().unwrap().is_none());
+        let mut events = matrix.scan().unwrap().unwrap();
+        assert_eq!(Some(Event::Press(1, 2)), events.next());
+        assert_eq!(None, events.next());
+        drop(events);
+        assert!(matrix.scan().unwrap().is_none());
+
+        // press (0, 0) and release (1, 2)
+        pressed[0].set(1);
+        pressed[1].set(0);
+        assert!(matrix.scan().unwrap().is_none());
+        let mut events = matrix.scan().unwrap().unwrap();
+        assert_eq!(Some(Event::Press(0, 0)), events.next());
+        assert_eq!(Some(Event::Release(1, 2)), events.next());
+        assert_eq!(None, events.next());
+        drop(events);
+
+        // the rows are deselected after the scan
+        assert_eq!(0, active_row.get());
+    }
+
+    #[test]
+    fn releases_first() {
+        let pressed = [Cell::new(0u32), Cell::new(0u32)];
+        let active_row = Cell::new(0u32);
+        let mut matrix = ClosureMatrix::<_, _, (), 2, 1>::new(
+            |mask| {
+                active_row.set(mask);
+                Ok::<(), Infallible>(())
+            },
+            || Ok(pressed[active_row.get().trailing_zeros() as usize % 2].get()),
+            (),
+        )
+        .unwrap();
+        matrix.debounce_mut().set_releases_first(true);
+
+        pressed[1].set(1 << 2);
+        assert!(matrix.scan().unwrap().is_none());
+        assert!(matrix.scan().unwrap().is_some());
+
+        // press (0, 0) and (1, 3), release (1, 2)
+        pressed[0].set(1);
+        pressed[1].set(1 << 3);
+        assert!(matrix.scan().unwrap().is_none());
+        let mut events = matrix.scan().unwrap().unwrap();
+        assert_eq!(Some(Event::Release(1, 2)), events.next());
+        assert_eq!(Some(Event::Press(0, 0)), events.next());
+        assert_eq!(Some(Event::Press(1, 3)), events.next());
+        assert_eq!(None, events.next());
+    }
+
+    /// A mock pin of a charlieplexed matrix where the key `(0, 2)`
+    /// is pressed.
+    struct MockPin<'a> {
+        idx: usize,
+        driven: &'a Cell<Option<usize>>,
+    }
+    impl TriStatePin for MockPin<'_> {
+        type Error = Infallible;
+        fn set_output_low(&mut self) -> Result<(), Infallible> {
+            self.driven.set(Some(self.idx));
+            Ok(())
+        }
+        fn set_input(&mut self) -> Result<(), Infallible> {
+            if self.driven.get() == Some(self.idx) {
+                self.driven.set(None);
+            }
+            Ok(())
+        }
+        fn is_low(&self) -> Result<bool, Infallible> {
+            Ok(self.driven.get() == Some(0) && self.idx == 2)
+        }
+    }
+
+    #[test]
+    fn charlie_matrix() {
+        let driven = Cell::new(None);
+        let pins = [0, 1, 2].map(|idx| MockPin {
+            idx,
+            driven: &driven,
+        });
+        let mut matrix = CharlieMatrix::<_, (), 3, 1>::new(pins, ()).unwrap();
+
+        assert!(matrix.scan().unwrap().is_none());
+        let mut events = matrix.scan().unwrap().unwrap();
+        assert_eq!(Some(Event::Press(0, 2)), events.next());
+        assert_eq!(None, events.next());
+        drop(events);
+        assert!(matrix.scan().unwrap().is_none());
+        assert_eq!(None, driven.get());
+    }
+
+    /// A mock row pin, setting the scanned row when low.
+    struct RowPin<'a> {
+        idx: usize,
+        scanned: &'a Cell<Option<usize>>,
+    }
+    impl OutputPin for RowPin<'_> {
+        type Error = Infallible;
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.scanned.set(Some(self.idx));
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            if self.scanned.get() == Some(self.idx) {
+                self.scanned.set(None);
+            }
+            Ok(())
+        }
+    }
+
+    /// A mock column pin, reading the pressed keys of the scanned row.
+    struct ColPin<'a> {
+        idx: usize,
+        scanned: &'a Cell<Option<usize>>,
+        pressed: &'a [Cell<u32>; 2],
+    }
+    impl InputPin for ColPin<'_> {
+        type Error = Infallible;
+        fn is_high(&self) -> Result<bool, Infallible> {
+            self.is_low().map(|low| !low)
+        }
+        fn is_low(&self) -> Result<bool, Infallible> {
+            Ok(match self.scanned.get() {
+                Some(r) => self.pressed[r].get() & 1 << self.idx != 0,
+                None => false,
+            })
+        }
+    }
+
+    #[test]
+    fn scan_step() {
+        let scanned = Cell::new(None);
+        let pressed = [Cell::new(0), Cell::new(0)];
+        let rows = [0, 1].map(|idx| RowPin {
+            idx,
+            scanned: &scanned,
+        });
+        let cols = [0, 1, 2, 3].map(|idx| ColPin {
+            idx,
+            scanned: &scanned,
+            pressed: &pressed,
+        });
+        let mut matrix = DebouncedMatrix::<_, _, (), 4, 2, 1>::new(cols, rows, ()).unwrap();
+
+        pressed[0].set(0b0001);
+        pressed[1].set(0b0100);
+        assert!(matches!(matrix.scan_step().unwrap(), ScanProgress::InProgress));
+        assert!(matches!(matrix.scan_step().unwrap(), ScanProgress::Complete(None)));
+        assert!(matches!(matrix.scan_step().unwrap(), ScanProgress::InProgress));
+        // released once its row is scanned: seen by the next scan
+        pressed[0].set(0);
+        let mut events = match matrix.scan_step().unwrap() {
+            ScanProgress::Complete(Some(events)) => events,
+            _ => panic!("the scan should be complete with events"),
+        };
+        assert_eq!(Some(Event::Press(0, 0)), events.next());
+        assert_eq!(Some(Event::Press(1, 2)), events.next());
+        assert_eq!(None, events.next());
+        drop(events);
+        assert_eq!(None, scanned.get());
+
+        // without any row, each step is a complete scan
+        let rows: [RowPin; 0] = [];
+        let cols = [0].map(|idx| ColPin {
+            idx,
+            scanned: &scanned,
+            pressed: &pressed,
+        });
+        let mut matrix = DebouncedMatrix::<_, _, (), 1, 0, 1>::new(cols, rows, ()).unwrap();
+        for _ in 0..3 {
+            assert!(matches!(matrix.scan_step().unwrap(), ScanProgress::Complete(None)));
+        }
+    }
+
+    #[test]
+    fn shift_reg_matrix() {
+        let scanned = Cell::new(None);
+        let words = [Cell::new(0u32), Cell::new(0u32)];
+        let rows = [0, 1].map(|idx| RowPin {
+            idx,
+            scanned: &scanned,
+        });
+        let mut matrix = ShiftRegMatrix::<_, _, (), 2, 1>::new(
+            rows,
+            || scanned.get().map_or(0, |r| words[r].get()),
+            (),
+        )
+        .unwrap();
+
+        assert!(matrix.scan().unwrap().is_none());
+
+        // press (0, 17) and (1, 3)
+        words[0].set(1 << 17);
+        words[1].set(1 << 3);
+        assert!(matrix.scan().unwrap().is_none());
+        let mut events = matrix.scan().unwrap().unwrap();
+        assert_eq!(Some(Event::Press(0, 17)), events.next());
+        assert_eq!(Some(Event::Press(1, 3)), events.next());
+        assert_eq!(None, events.next());
+        drop(events);
+        assert!(matrix.scan().unwrap().is_none());
+
+        // release (0, 17)
+        words[0].set(0);
+        assert!(matrix.scan().unwrap().is_none());
+        let mut events = matrix.scan().unwrap().unwrap();
+        assert_eq!(Some(Event::Release(0, 17)), events.next());
+        assert_eq!(None, events.next());
+        drop(events);
+
+        // the rows are deselected after the scan
+        assert_eq!(None, scanned.get());
+    }
+
+    #[test]
+    fn prime() {
+        let scanned = Cell::new(None);
+        let pressed = [Cell::new(0), Cell::new(0)];
+        let rows = [0, 1].map(|idx| RowPin {
+            idx,
+            scanned: &scanned,
+        });
+        let cols = [0, 1].map(|idx| ColPin {
+            idx,
+            scanned: &scanned,
+            pressed: &pressed,
+        });
+        let mut matrix = DebouncedMatrix::<_, _, (), 2, 2, 2>::new(cols, rows, ()).unwrap();
+
+        // a transient, gone by the scan after the wake up
+        pressed[1].set(0b10);
+        assert!(matrix.scan().unwrap().is_none());
+        assert!(matrix.scan().unwrap().is_none());
+        pressed[1].set(0);
+        matrix.prime();
+        assert!(matrix.scan().unwrap().is_none());
+
+        // the debouncing progress before the wake up is forgotten
+        pressed[1].set(0b10);
+        assert!(matrix.scan().unwrap().is_none());
+        assert!(matrix.scan().unwrap().is_none());
+        matrix.prime();
+        assert!(matrix.scan().unwrap().is_none());
+        assert!(matrix.scan().unwrap().is_none());
+        let mut events = matrix.scan().unwrap().unwrap();
+        assert_eq!(Some(Event::Press(1, 1)), events.next());
+        assert_eq!(None, events.next());
+        drop(events);
+
+        // and so is an interrupted cooperative scan
+        pressed[0].set(0b01);
+        assert!(matches!(matrix.scan_step().unwrap(), ScanProgress::InProgress));
+        matrix.prime();
+        assert!(matches!(matrix.scan_step().unwrap(), ScanProgress::InProgress));
+    }
+
+    /// A mock pin failing, with its index as error, once `ok` calls
+    /// have succeeded. The pin reads high.
+    struct FailingPin {
+        idx: usize,
+        ok: Cell<usize>,
+    }
+    impl FailingPin {
+        fn new(idx: usize, ok: usize) -> Self {
+            Self {
+                idx,
+                ok: Cell::new(ok),
+            }
+        }
+        fn call(&self) -> Result<(), usize> {
+            match self.ok.get() {
+                0 => Err(self.idx),
+                ok => {
+                    self.ok.set(ok - 1);
+                    Ok(())
+                }
+            }
         }
     }
+    impl OutputPin for FailingPin {
+        type Error = usize;
+        fn set_low(&mut self) -> Result<(), usize> {
+            self.call()
+        }
+        fn set_high(&mut self) -> Result<(), usize> {
+            self.call()
+        }
+    }
+    impl InputPin for FailingPin {
+        type Error = usize;
+        fn is_high(&self) -> Result<bool, usize> {
+            self.call().map(|_| true)
+        }
+        fn is_low(&self) -> Result<bool, usize> {
+            self.call().map(|_| false)
+        }
+    }
+    impl TriStatePin for FailingPin {
+        type Error = usize;
+        fn set_output_low(&mut self) -> Result<(), usize> {
+            self.call()
+        }
+        fn set_input(&mut self) -> Result<(), usize> {
+            self.call()
+        }
+        fn is_low(&self) -> Result<bool, usize> {
+            self.call().map(|_| false)
+        }
+    }
+
+    #[test]
+    fn matrix_error() {
+        let matrix = |rows: [usize; 2], cols: [usize; 2]| {
+            DebouncedMatrix::<_, _, (), 2, 2, 1>::new(
+                [FailingPin::new(0, cols[0]), FailingPin::new(1, cols[1])],
+                [FailingPin::new(2, rows[0]), FailingPin::new(3, rows[1])],
+                (),
+            )
+        };
+        let res = matrix([0, 0], [9, 9]);
+        assert_eq!(Some(MatrixError::Clear { row: 0, error: 2 }), res.err());
+
+        // the scan of row 0: set low, read the columns, set high
+        let mut m = matrix([1, 9], [9, 9]).unwrap();
+        let err = MatrixError::SelectRow { row: 0, error: 2 };
+        assert_eq!(Some(err), m.scan().err());
+        let mut m = matrix([9, 9], [9, 0]).unwrap();
+        let err = MatrixError::ReadColumn { col: 1, error: 1 };
+        assert_eq!(Some(err), m.scan().err());
+        let mut m = matrix([9, 2], [9, 9]).unwrap();
+        let err = MatrixError::DeselectRow { row: 1, error: 3 };
+        assert_eq!(Some(err), m.scan().err());
+    }
+
+    #[test]
+    fn pin_matrices_error() {
+        let rows = [FailingPin::new(0, 9), FailingPin::new(1, 1)];
+        let mut matrix = ShiftRegMatrix::<_, _, (), 2, 1>::new(rows, || 0, ()).unwrap();
+        let err = MatrixError::SelectRow { row: 1, error: 1 };
+        assert_eq!(Some(err), matrix.scan().err());
+
+        let pins = [0, 1, 2].map(|idx| FailingPin::new(idx, 1));
+        let mut matrix = CharlieMatrix::<_, (), 3, 1>::new(pins, ()).unwrap();
+        let err = MatrixError::SelectRow { row: 0, error: 0 };
+        assert_eq!(Some(err), matrix.scan().err());
+        let pins = [
+            FailingPin::new(0, 9),
+            FailingPin::new(1, 9),
+            FailingPin::new(2, 1),
+        ];
+        let mut matrix = CharlieMatrix::<_, (), 3, 1>::new(pins, ()).unwrap();
+        let err = MatrixError::ReadColumn { col: 2, error: 2 };
+        assert_eq!(Some(err), matrix.scan().err());
+    }
+
+    #[test]
+    fn row_short() {
+        let scanned = Cell::new(None);
+        let pressed = [Cell::new(0), Cell::new(0)];
+        let rows = [0, 1].map(|idx| RowPin {
+            idx,
+            scanned: &scanned,
+        });
+        let cols = [0, 1, 2, 3].map(|idx| ColPin {
+            idx,
+            scanned: &scanned,
+            pressed: &pressed,
+        });
+        let mut matrix = DebouncedMatrix::<_, _, (), 4, 2, 1>::new(cols, rows, ()).unwrap();
+        matrix.debounce_mut().set_max_changes(Some(3));
+
+        // a whole row is shorted: flagged, without events
+        pressed[1].set(0b1111);
+        assert!(matrix.scan().unwrap().is_none());
+        assert!(matrix.scan().unwrap().is_none());
+        assert_eq!(
+            Some(Anomaly { changed: 4 }),
+            matrix.debounce().last_scan_anomaly()
+        );
+        assert!(matrix.scan().unwrap().is_none());
+        assert!(matrix.scan().unwrap().is_none());
+        assert_eq!(
+            Some(Anomaly { changed: 4 }),
+            matrix.debounce().last_scan_anomaly()
+        );
+
+        // the short disappears
+        pressed[1].set(0);
+        assert!(matrix.scan().unwrap().is_none());
+        assert_eq!(None, matrix.debounce().last_scan_anomaly());
+
+        // a normal press
+        pressed[1].set(0b0100);
+        assert!(matrix.scan().unwrap().is_none());
+        let mut events = matrix.scan().unwrap().unwrap();
+        assert_eq!(Some(Event::Press(1, 2)), events.next());
+        assert_eq!(None, events.next());
+        drop(events);
+        assert_eq!(None, matrix.debounce().last_scan_anomaly());
+    }
 }
@@ -1,9 +1,12 @@
 //! Key code definitions.
 
+use core::ops::RangeInclusive;
+
 #[allow(missing_docs)]
 /// Define a key code according to the HID specification.  Their names
 /// correspond to the american QWERTY layout.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum KeyCode {
     /// The "no" key, a placeholder to express nothing.
@@ -258,15 +261,74 @@ pub enum KeyCode {
 }
 
 impl KeyCode {
+    /// The usage codes of the modifiers, from `LCtrl` to `RGui`.
+    pub const MODIFIER_RANGE: RangeInclusive<u8> = KeyCode::LCtrl as u8..=KeyCode::RGui as u8;
+
+    /// The usage codes of the letters, from `A` to `Z`.
+    pub const LETTER_RANGE: RangeInclusive<u8> = KeyCode::A as u8..=KeyCode::Z as u8;
+
     /// Returns `true` if the key code corresponds to a modifier (sent
     /// separately on the USB HID report).
-    pub fn is_modifier(self) -> bool {
-        KeyCode::LCtrl <= self && self <= KeyCode::RGui
+    pub const fn is_modifier(self) -> bool {
+        KeyCode::LCtrl as u8 <= self as u8 && self as u8 <= KeyCode::RGui as u8
+    }
+
+    /// Returns `true` if the key code corresponds to a letter.
+    pub const fn is_letter(self) -> bool {
+        KeyCode::A as u8 <= self as u8 && self as u8 <= KeyCode::Z as u8
+    }
+
+    /// Returns the character typed by the key code on an US QWERTY
+    /// layout, with or without shift, or `None` for the non printing
+    /// key codes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use keyberon::key_code::KeyCode;
+    /// assert_eq!(KeyCode::A.to_char(false), Some('a'));
+    /// assert_eq!(KeyCode::Kb1.to_char(true), Some('!'));
+    /// assert_eq!(KeyCode::LShift.to_char(false), None);
+    /// ```
+    pub const fn to_char(self, shifted: bool) -> Option<char> {
+        use KeyCode::*;
+        let kc = self as u8;
+        if A as u8 <= kc && kc <= Z as u8 {
+            let base = if shifted { b'A' } else { b'a' };
+            return Some((base + (kc - A as u8)) as char);
+        }
+        let (normal, shift) = match self {
+            Kb1 => ('1', '!'),
+            Kb2 => ('2', '@'),
+            Kb3 => ('3', '#'),
+            Kb4 => ('4', '$'),
+            Kb5 => ('5', '%'),
+            Kb6 => ('6', '^'),
+            Kb7 => ('7', '&'),
+            Kb8 => ('8', '*'),
+            Kb9 => ('9', '('),
+            Kb0 => ('0', ')'),
+            Space => (' ', ' '),
+            Minus => ('-', '_'),
+            Equal => ('=', '+'),
+            LBracket => ('[', '{'),
+            RBracket => (']', '}'),
+            Bslash => ('\\', '|'),
+            NonUsHash => ('#', '~'),
+            SColon => (';', ':'),
+            Quote => ('\'', '"'),
+            Grave => ('`', '~'),
+            Comma => (',', '<'),
+            Dot => ('.', '>'),
+            Slash => ('/', '?'),
+            _ => return None,
+        };
+        Some(if shifted { shift } else { normal })
     }
 
     /// Returns the byte with the bit corresponding to the USB HID
     /// modifier bitfield set.
-    pub fn as_modifier_bit(self) -> u8 {
+    pub const fn as_modifier_bit(self) -> u8 {
         if self.is_modifier() {
             1 << (self as u8 - KeyCode::LCtrl as u8)
         } else {
@@ -315,9 +377,122 @@ impl KbHidReport {
                 .unwrap_or_else(|| self.set_all(ErrorRollOver)),
         }
     }
+    /// Add the given key code to the report if there is a free slot
+    /// for it, else it is ignored. The modifiers are always added.
+    pub fn pressed_weak(&mut self, kc: KeyCode) {
+        if kc.is_modifier() {
+            self.0[0] |= kc.as_modifier_bit();
+        } else if kc != KeyCode::No && !self.0[2..].contains(&(kc as u8)) {
+            if let Some(c) = self.0[2..].iter_mut().find(|c| **c == 0) {
+                *c = kc as u8;
+            }
+        }
+    }
     fn set_all(&mut self, kc: KeyCode) {
         for c in &mut self.0[2..] {
             *c = kc as u8;
         }
     }
 }
+
+/// Builds the reports, and gives them only when they differ from
+/// the last one, to avoid redundant USB transfers.
+///
+/// The initial report is the empty one: nothing is given until a key
+/// is pressed.
+///
+/// ```
+/// use keyberon::key_code::{KeyCode::*, ReportTracker};
+///
+/// let mut tracker = ReportTracker::default();
+/// assert_eq!(None, tracker.update(core::iter::empty()));
+/// assert_eq!(Some([0, 0, 4, 0, 0, 0, 0, 0]), tracker.update([A].iter().copied()));
+/// assert_eq!(None, tracker.update([A].iter().copied()));
+/// ```
+#[derive(Default, Debug, Clone)]
+pub struct ReportTracker {
+    last: KbHidReport,
+}
+
+impl ReportTracker {
+    /// Builds the report of the given key codes (typically
+    /// `Layout::keycodes`), and returns it if it is different from
+    /// the last returned one.
+    pub fn update(&mut self, keycodes: impl IntoIterator<Item = KeyCode>) -> Option<[u8; 8]> {
+        let report: KbHidReport = keycodes.into_iter().collect();
+        if report == self.last {
+            None
+        } else {
+            self.last = report;
+            Some(self.last.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeyCode::*;
+    use super::ReportTracker;
+
+    #[test]
+    fn to_char() {
+        assert_eq!(Some('a'), A.to_char(false));
+        assert_eq!(Some('A'), A.to_char(true));
+        assert_eq!(Some('z'), Z.to_char(false));
+        assert_eq!(Some('1'), Kb1.to_char(false));
+        assert_eq!(Some('!'), Kb1.to_char(true));
+        assert_eq!(Some('\''), Quote.to_char(false));
+        assert_eq!(Some('"'), Quote.to_char(true));
+        assert_eq!(None, LShift.to_char(false));
+        assert_eq!(None, LShift.to_char(true));
+        assert_eq!(None, Enter.to_char(false));
+    }
+
+    #[test]
+    fn international_usage_codes() {
+        assert_eq!(0x32, NonUsHash as u8);
+        assert_eq!(0x64, NonUsBslash as u8);
+        assert_eq!(0x87, Intl1 as u8);
+        assert_eq!(0x8f, Intl9 as u8);
+        assert_eq!(0x90, Lang1 as u8);
+        assert_eq!(0x98, Lang9 as u8);
+    }
+
+    #[test]
+    fn ranges() {
+        use super::KeyCode;
+        let modifiers = [LCtrl, LShift, LAlt, LGui, RCtrl, RShift, RAlt, RGui];
+        assert_eq!(8, KeyCode::MODIFIER_RANGE.count());
+        for (code, kc) in KeyCode::MODIFIER_RANGE.zip(modifiers.iter()) {
+            assert_eq!(code, *kc as u8);
+            assert!(kc.is_modifier());
+        }
+        assert_eq!(26, KeyCode::LETTER_RANGE.count());
+        assert_eq!(A as u8, *KeyCode::LETTER_RANGE.start());
+        assert_eq!(Z as u8, *KeyCode::LETTER_RANGE.end());
+        for kc in [A, M, Z].iter() {
+            assert!(KeyCode::LETTER_RANGE.contains(&(*kc as u8)));
+            assert!(kc.is_letter());
+            assert!(!kc.is_modifier());
+        }
+        for kc in [No, Kb1, Enter, LCtrl, RGui].iter() {
+            assert!(!KeyCode::LETTER_RANGE.contains(&(*kc as u8)));
+            assert!(!kc.is_letter());
+        }
+        assert!(!KeyCode::MODIFIER_RANGE.contains(&(MediaCalc as u8)));
+    }
+
+    #[test]
+    fn report_tracker() {
+        let mut tracker = ReportTracker::default();
+        assert_eq!(None, tracker.update([].iter().copied()));
+        let report = tracker.update([LShift, A].iter().copied());
+        assert_eq!(Some([2, 0, 4, 0, 0, 0, 0, 0]), report);
+        assert_eq!(None, tracker.update([LShift, A].iter().copied()));
+        let report = tracker.update([LShift].iter().copied());
+        assert_eq!(Some([2, 0, 0, 0, 0, 0, 0, 0]), report);
+        let report = tracker.update([].iter().copied());
+        assert_eq!(Some([0; 8]), report);
+        assert_eq!(None, tracker.update([].iter().copied()));
+    }
+}
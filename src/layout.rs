@@ -8,13 +8,23 @@
 ///       to KeyCodes as well: `,` becomes `KeyCode::Commma`, `2` becomes `KeyCode::Kb2`, `/` becomes `KeyCode::Slash`
 ///     - Characters which require shifted keys are converted to `Action::MultipleKeyCodes(&[LShift, <character>])`:
 ///       `!` becomes `Action::MultipleKeyCodes(&[LShift, Kb1])` etc
+///     - With a leading `#raw` directive, before the layers, the shifted characters are not wrapped
+///       with `LShift`, for the hosts not using a US layout: `!` becomes `KeyCode::Kb1`
 ///     - Characters special to the rust parser (parentheses, brackets, braces, quotes, apostrophes, underscores, backslashes and backticks)
 ///       left alone cause parsing errors and as such have to be enclosed by apostrophes: `'['` becomes `KeyCode::LBracket`,
 ///       `'\''` becomes `KeyCode::Quote`, `'\\'` becomes `KeyCode::BSlash`
 /// - [`Action::NoOp`]: Lowercase `n`
 /// - [`Action::Trans`]: Lowercase `t`
 /// - [`Action::Layer`]: A number in parentheses: `(1)`, `(4 - 2)`, `(0x4u8 as usize)`
-/// - [`Action::MultipleActions`]: Actions in brackets: `[LCtrl S]`, `[LAlt LCtrl C]`, `[(2) B {Action::NoOp}]`
+/// - [`Action::MultipleActions`]: Actions in brackets: `[LCtrl '[']`, `[(2) B {Action::NoOp}]`
+/// - [`Action::MultipleKeyCodes`]: Keycodes only in nested brackets, flattened: `[[LCtrl LAlt] C]`
+///   becomes `Action::MultipleKeyCodes(&[LCtrl, LAlt, C])`, while `[LCtrl S]` stays
+///   `Action::MultipleActions`
+/// - [`Action::MultipleKeyCodes`] with modifiers: a keycode wrapped by `ctrl`, `shift`, `alt` or `gui`
+///   (the left modifiers) in braces: `{ctrl(C)}` becomes `Action::MultipleKeyCodes(&[LCtrl, C])`,
+///   `{ctrl(shift(Tab))}` becomes `Action::MultipleKeyCodes(&[LCtrl, LShift, Tab])`
+/// - [`Action::HoldTap`] as a mod-tap: `mt` with the hold and the tap keycodes in braces: `{mt(LCtrl, A)}` becomes
+///   `action::mt(&k(LCtrl), &k(A))`
 /// - Other `Action`s: anything in braces (`{}`) is copied unchanged to the final layout - `{ Action::Custom(42) }`
 ///   simply becomes `Action::Custom(42)`
 ///
@@ -38,7 +48,7 @@
 ///     {
 ///         [ Tab    1 2 3 4 5   6 7 8 9 0 BSpace  ]
 ///         [ LCtrl  ! @ # $ %   ^ & * '(' ')' -   ]
-///         [ LShift n n n n n   n n n n n [LAlt A]]
+///         [ LShift n n n n n   n n {ctrl(X)} {ctrl(C)} {ctrl(V)} [LAlt A]]
 ///         [ n n LGui (2) t t   t t t RAlt n n    ]
 ///     }
 ///     // ...
@@ -47,8 +57,8 @@
 pub use keyberon_macros::layout;
 pub use keyberon_macros::*;
 
-use crate::action::{Action, HoldTapConfig};
-use crate::key_code::KeyCode;
+use crate::action::{Action, HoldTapConfig, MacroStep};
+use crate::key_code::{KbHidReport, KeyCode};
 use arraydeque::ArrayDeque;
 use heapless::Vec;
 
@@ -66,13 +76,179 @@ use State::*;
 pub type Layers<T, const C: usize, const R: usize, const L: usize> = [[[Action<T>; C]; R]; L];
 
 type Deque = ArrayDeque<[Stacked; 16], arraydeque::behavior::Wrapping>;
+type CustomEvents<T> = ArrayDeque<[CustomEvent<T>; 16], arraydeque::behavior::Wrapping>;
+
+/// The maximum number of ticks between the `Layout::event` call of a
+/// key press and the report of its key code by `Layout::keycodes`,
+/// for a `KeyCode` action when nothing else is waiting: no event
+/// stacked before it, no waiting hold-tap, no combo and no minimum
+/// press duration.
+///
+/// Each tick processes one stacked event: when several events are
+/// registered at once, the `n`th one is processed after
+/// `n * LATENCY_TICKS` ticks.
+pub const LATENCY_TICKS: u16 = 1;
+
+/// The coordinates used for the key codes injected with
+/// `Layout::press_keycode`. No physical key can be at these
+/// coordinates.
+const INJECTED_COORD: (u8, u8) = (u8::MAX, u8::MAX);
+
+/// The coordinates used for the key codes pressed by an
+/// `Action::Macro`.
+const MACRO_COORD: (u8, u8) = (u8::MAX, u8::MAX - 1);
+
+/// The coordinates of a key without logical position, as given by
+/// `split::EventRemapper` for a key missing from its table. The
+/// events at these coordinates are ignored by `Layout::event`.
+pub const UNMAPPED_COORD: (u8, u8) = (u8::MAX, u8::MAX - 2);
 
 /// Indicates that the layout doesn't contain user-defined actions ([Action::Custom])
 pub type NoCustom = core::convert::Infallible;
 
+/// Copies `src` into `dst`, reordering the keys according to `map`.
+///
+/// `map[i][j]` gives the coordinates in `src` of the action that
+/// must be at the coordinates (i, j) in `dst`. This allows to write
+/// the layout in the logical order, and to remap it once at startup
+/// to the physical order of the switch matrix (for example when the
+/// PCB is wired in a serpentine), instead of transforming every
+/// `Event`. If a coordinate of `map` is out of `src`, the
+/// corresponding action is `Action::NoOp`.
+///
+/// # Example
+///
+/// ```
+/// use keyberon::action::{k, Action};
+/// use keyberon::key_code::KeyCode::*;
+/// use keyberon::layout::{remap_into, Layers, NoCustom};
+///
+/// static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[k(A), k(B)]]];
+/// let mut remapped: Layers<NoCustom, 2, 1, 1> = [[[Action::NoOp; 2]; 1]; 1];
+/// remap_into(&LAYERS, &[[(0, 1), (0, 0)]], &mut remapped);
+/// assert_eq!(remapped, [[[k(B), k(A)]]]);
+/// ```
+pub fn remap_into<T: Copy, const C: usize, const R: usize, const L: usize>(
+    src: &Layers<T, C, R, L>,
+    map: &[[(u8, u8); C]; R],
+    dst: &mut Layers<T, C, R, L>,
+) {
+    for (src_layer, dst_layer) in src.iter().zip(dst.iter_mut()) {
+        for (map_row, dst_row) in map.iter().zip(dst_layer.iter_mut()) {
+            for (&(i, j), dst_action) in map_row.iter().zip(dst_row.iter_mut()) {
+                *dst_action = src_layer
+                    .get(i as usize)
+                    .and_then(|r| r.get(j as usize))
+                    .copied()
+                    .unwrap_or(Action::NoOp);
+            }
+        }
+    }
+}
+
+/// The modifiers, in the order of the USB HID modifier bitfield.
+const MODIFIERS: [KeyCode; 8] = [
+    KeyCode::LCtrl,
+    KeyCode::LShift,
+    KeyCode::LAlt,
+    KeyCode::LGui,
+    KeyCode::RCtrl,
+    KeyCode::RShift,
+    KeyCode::RAlt,
+    KeyCode::RGui,
+];
+
+/// The hand of a key, see `Layout::set_hand_map`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hand {
+    /// A key of the left hand.
+    Left,
+    /// A key of the right hand.
+    Right,
+}
+
+/// The handling of a current layer beyond the last layer, when the
+/// active layers are summed. See `Layout::set_layer_overflow_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerOverflowPolicy {
+    /// The layer is kept: as it doesn't exist, its keys do nothing.
+    Ignore,
+    /// The layer wraps around, modulo the number of layers.
+    Wrap,
+    /// The last layer is used.
+    Clamp,
+}
+
+/// A combo: pressing all the `keys` together performs the `action`.
+///
+/// The keys are physical coordinates, thus a combo is active
+/// whatever the current layer is, and its action doesn't depend on
+/// the layer. The action is resolved when the combo fires, with the
+/// layers active at this time: an `Action::Trans` combo action
+/// performs the action of the first key of the combo on the current
+/// layer, and the `Action::Layer` actions are layer modifiers held by
+/// the combo. The combo action is released as soon as one of its
+/// keys is released: a combo with an `Action::Layer` action is a
+/// momentary layer, active while the whole chord is held.
+///
+/// See `Layout::set_combos`.
+#[derive(Debug)]
+pub struct Combo<T: 'static> {
+    /// The coordinates of the keys to press together.
+    pub keys: &'static [(u8, u8)],
+    /// The action performed by the combo.
+    pub action: &'static Action<T>,
+}
+
+/// A key override: while the `trigger` key code and all the
+/// modifiers of `with_mods` are active, the key codes of the
+/// `replacement` action are reported instead of `trigger`, and the
+/// modifiers of `suppressed_mods` are not reported.
+///
+/// The modifier masks use the USB HID modifier bitfield (see
+/// `KeyCode::as_modifier_bit`). For example, "Shift + Backspace =
+/// Delete" is:
+///
+/// ```
+/// use keyberon::action::k;
+/// use keyberon::key_code::KeyCode::*;
+/// use keyberon::layout::{KeyOverride, NoCustom};
+///
+/// static SHIFT_BSPACE: KeyOverride<NoCustom> = KeyOverride {
+///     trigger: BSpace,
+///     with_mods: LShift.as_modifier_bit(),
+///     replacement: &k(Delete),
+///     suppressed_mods: LShift.as_modifier_bit(),
+/// };
+/// ```
+///
+/// Only the key codes of `replacement` are used (see
+/// `Action::key_codes`). See `Layout::set_key_overrides`.
+#[derive(Debug)]
+pub struct KeyOverride<T: 'static> {
+    /// The key code to replace.
+    pub trigger: KeyCode,
+    /// The modifiers that must be active.
+    pub with_mods: u8,
+    /// The action giving the replacement key codes.
+    pub replacement: &'static Action<T>,
+    /// The modifiers that are not reported while the override is
+    /// active.
+    pub suppressed_mods: u8,
+}
+
 /// The layout manager. It takes `Event`s and `tick`s as input, and
 /// generate keyboard reports.
-pub struct Layout<T, const C: usize, const R: usize, const L: usize>
+///
+/// The generic parameters are the same as the ones of `Layers`, and
+/// `H`, the number of registered events kept in the history (see
+/// `Layout::recent_events`). By default, no history is kept.
+///
+/// The layout only references the layers and the custom actions, of
+/// type `&'static T`: it is `Send` and `Sync` if `T` is `Sync`. Thus,
+/// it can be shared between an interrupt and the main loop behind a
+/// mutex (as `critical_section::Mutex<RefCell<Layout<..>>>`).
+pub struct Layout<T, const C: usize, const R: usize, const L: usize, const H: usize = 0>
 where
     T: 'static,
 {
@@ -81,10 +257,89 @@ where
     states: Vec<State<T>, 64>,
     waiting: Option<WaitingState<T>>,
     deque: Deque,
+    keycode_remap: Option<&'static [KeyCode; 256]>,
+    holdtap_enabled: bool,
+    require_prior_idle: u16,
+    /// Number of ticks since the last key press processed.
+    last_press: u16,
+    last_keycode: Option<KeyCode>,
+    last_mods: u8,
+    min_press_ticks: u16,
+    macro_player: Option<MacroPlayer>,
+    tri_layer: Option<(usize, usize, usize)>,
+    layer_overflow: LayerOverflowPolicy,
+    cosmetic_layers: Option<&'static [bool; L]>,
+    auto_shift: Option<(u16, &'static [bool; L])>,
+    hand_map: Option<fn((u8, u8)) -> Hand>,
+    custom_predicate: Option<fn(&T) -> bool>,
+    combos: &'static [Combo<T>],
+    combo_term: u16,
+    active_combos: Vec<usize, 8>,
+    custom_events: CustomEvents<T>,
+    cycles: Vec<((u8, u8), usize), 8>,
+    tap_locks: Vec<TapLock, 8>,
+    default_actions: [&'static Action<T>; L],
+    key_overrides: &'static [KeyOverride<T>],
+    forced_mods: u8,
+    modifier_timeout: Option<u16>,
+    inactive_ticks: u16,
+    stuck_threshold: Option<u16>,
+    stuck_hook: Option<fn((u8, u8), KeyCode)>,
+    paused: bool,
+    lock: Option<(&'static [(u8, u8)], usize)>,
+    pressed: Vec<(u8, u8), 64>,
+    unmatched_releases: u32,
+    history: [Option<Event>; H],
+    #[cfg(feature = "holdtap-stats")]
+    holdtap_stats: HoldTapStats,
+}
+
+/// The runtime state of a `Layout`: the pressed keys, the active
+/// layers, the waiting hold-tap and the stacked events. See
+/// `Layout::snapshot` and `Layout::restore`.
+///
+/// The configuration of the layout (combos, remapping...) is not
+/// part of the snapshot.
+#[derive(Debug)]
+pub struct LayoutSnapshot<T: 'static> {
+    default_layer: usize,
+    states: Vec<State<T>, 64>,
+    waiting: Option<WaitingState<T>>,
+    deque: Deque,
+    active_combos: Vec<usize, 8>,
+    cycles: Vec<((u8, u8), usize), 8>,
+    tap_locks: Vec<TapLock, 8>,
+    last_keycode: Option<KeyCode>,
+    last_mods: u8,
+    macro_player: Option<MacroPlayer>,
+    lock: Option<(&'static [(u8, u8)], usize)>,
+    pressed: Vec<(u8, u8), 64>,
+    inactive_ticks: u16,
+    last_press: u16,
+}
+
+/// Statistics on the resolution of the `HoldTap` actions.
+///
+/// Useful to tune the timeouts and the configurations of the
+/// `HoldTap` actions (for home row mods for example).
+#[cfg(feature = "holdtap-stats")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HoldTapStats {
+    /// Number of `HoldTap` resolved as a tap.
+    pub taps: u32,
+    /// Number of `HoldTap` resolved as a hold.
+    pub holds: u32,
+    /// Number of `HoldTap` resolved as a hold because the timeout
+    /// elapsed. These resolutions are also counted in `holds`.
+    pub timeouts: u32,
 }
 
 /// An event on the key matrix.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+///
+/// The events are ordered with the presses first, and then by
+/// coordinates.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Event {
     /// Press event with coordinates (i, j).
     Press(u8, u8),
@@ -92,6 +347,16 @@ pub enum Event {
     Release(u8, u8),
 }
 impl Event {
+    /// Creates a press event from the coordinates (i, j).
+    pub fn press((i, j): (u8, u8)) -> Self {
+        Event::Press(i, j)
+    }
+
+    /// Creates a release event from the coordinates (i, j).
+    pub fn release((i, j): (u8, u8)) -> Self {
+        Event::Release(i, j)
+    }
+
     /// Returns the coordinates (i, j) of the event.
     pub fn coord(self) -> (u8, u8) {
         match self {
@@ -143,6 +408,7 @@ impl Event {
 
 /// Event from custom action.
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CustomEvent<T: 'static> {
     /// No custom action.
     NoEvent,
@@ -151,12 +417,22 @@ pub enum CustomEvent<T: 'static> {
     /// The given custom action key is released.
     Release(&'static T),
 }
+impl<T> Copy for CustomEvent<T> {}
+impl<T> Clone for CustomEvent<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
 impl<T> CustomEvent<T> {
     /// Update an event according to a new event.
     ///
-    ///The event can only be modified in the order `NoEvent < Press <
-    /// Release`
-    fn update(&mut self, e: Self) {
+    /// The event can only be modified in the order `NoEvent < Press <
+    /// Release`: a release replaces a press, and a press replaces
+    /// `NoEvent`. This is how the custom events of a single tick are
+    /// merged into the one returned by `Layout::tick`: the other ones
+    /// are lost, but can be retrieved with
+    /// `Layout::take_custom_events`.
+    pub fn update(&mut self, e: Self) {
         use CustomEvent::*;
         match (&e, &self) {
             (Release(_), NoEvent) | (Release(_), Press(_)) => *self = e,
@@ -173,9 +449,56 @@ impl<T> Default for CustomEvent<T> {
 
 #[derive(Debug, Eq, PartialEq)]
 enum State<T: 'static> {
-    NormalKey { keycode: KeyCode, coord: (u8, u8) },
-    LayerModifier { value: usize, coord: (u8, u8) },
-    Custom { value: &'static T, coord: (u8, u8) },
+    NormalKey {
+        keycode: KeyCode,
+        coord: (u8, u8),
+        /// Number of ticks since the press.
+        held: u16,
+    },
+    WeakKey {
+        keycode: KeyCode,
+        coord: (u8, u8),
+    },
+    OneShot {
+        keycode: KeyCode,
+        /// The key arming the one shot.
+        coord: (u8, u8),
+    },
+    Latched {
+        keycode: KeyCode,
+    },
+    LayerModifier {
+        value: usize,
+        fallback: Option<usize>,
+        coord: (u8, u8),
+    },
+    Custom {
+        value: &'static T,
+        coord: (u8, u8),
+    },
+    CustomPulse {
+        value: &'static T,
+        remaining: u16,
+    },
+    StickyLayer {
+        value: usize,
+        coord: (u8, u8),
+        /// Remaining ticks for a tap arming the one shot layer.
+        remaining: u16,
+        /// Another key was pressed while the layer was active.
+        used: bool,
+    },
+    OneShotLayer {
+        value: usize,
+    },
+    RepeatKey {
+        keycode: KeyCode,
+        coord: (u8, u8),
+        interval: u16,
+        /// Remaining ticks before the next release or press.
+        remaining: u16,
+        down: bool,
+    },
 }
 impl<T> Copy for State<T> {}
 impl<T> Clone for State<T> {
@@ -186,55 +509,257 @@ impl<T> Clone for State<T> {
 impl<T: 'static> State<T> {
     fn keycode(&self) -> Option<KeyCode> {
         match self {
-            NormalKey { keycode, .. } => Some(*keycode),
+            NormalKey { keycode, .. }
+            | WeakKey { keycode, .. }
+            | OneShot { keycode, .. }
+            | Latched { keycode }
+            | RepeatKey {
+                keycode,
+                down: true,
+                ..
+            } => Some(*keycode),
             _ => None,
         }
     }
-    fn release(&self, c: (u8, u8), custom: &mut CustomEvent<T>) -> Option<Self> {
+    fn keycode_weak(&self) -> Option<(KeyCode, bool)> {
+        match self {
+            WeakKey { keycode, .. } => Some((*keycode, true)),
+            s => s.keycode().map(|kc| (kc, false)),
+        }
+    }
+    fn release(
+        &self,
+        c: (u8, u8),
+        custom: &mut CustomEvent<T>,
+        all: &mut CustomEvents<T>,
+    ) -> Option<Self> {
         match *self {
-            NormalKey { coord, .. } | LayerModifier { coord, .. } if coord == c => None,
+            NormalKey { coord, .. }
+            | WeakKey { coord, .. }
+            | LayerModifier { coord, .. }
+            | RepeatKey { coord, .. }
+                if coord == c =>
+            {
+                None
+            }
+            StickyLayer {
+                value,
+                coord,
+                remaining,
+                used,
+            } if coord == c => match (used, remaining) {
+                (false, 1..=u16::MAX) => Some(OneShotLayer { value }),
+                _ => None,
+            },
             Custom { value, coord } if coord == c => {
+                let _ = all.push_back(CustomEvent::Release(value));
                 custom.update(CustomEvent::Release(value));
                 None
             }
             _ => Some(*self),
         }
     }
+    /// Returns `true` if the state is the key code pressed, weakly
+    /// or not, by the given key.
+    fn is_key(&self, coord: (u8, u8), keycode: KeyCode) -> bool {
+        match *self {
+            NormalKey {
+                keycode: kc,
+                coord: c,
+                ..
+            }
+            | WeakKey {
+                keycode: kc,
+                coord: c,
+            } => kc == keycode && c == coord,
+            _ => false,
+        }
+    }
+    /// The key of the matrix holding the state, if any.
+    fn coord(&self) -> Option<(u8, u8)> {
+        match *self {
+            NormalKey { coord, .. }
+            | WeakKey { coord, .. }
+            | LayerModifier { coord, .. }
+            | StickyLayer { coord, .. }
+            | Custom { coord, .. }
+            | RepeatKey { coord, .. }
+                if coord != INJECTED_COORD && coord != MACRO_COORD =>
+            {
+                Some(coord)
+            }
+            _ => None,
+        }
+    }
     fn get_layer(&self) -> Option<usize> {
         match self {
-            LayerModifier { value, .. } => Some(*value),
+            LayerModifier { value, .. } | StickyLayer { value, .. } | OneShotLayer { value } => {
+                Some(*value)
+            }
             _ => None,
         }
     }
+    fn tick(&mut self) {
+        match self {
+            CustomPulse { remaining, .. } | StickyLayer { remaining, .. } => {
+                *remaining = remaining.saturating_sub(1)
+            }
+            RepeatKey {
+                interval,
+                remaining,
+                down,
+                ..
+            } => {
+                *remaining = remaining.saturating_sub(1);
+                if *remaining == 0 {
+                    *down = !*down;
+                    *remaining = if *down {
+                        interval.saturating_sub(1).max(1)
+                    } else {
+                        1
+                    };
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Writes an action compactly, see `Layout::write_layer`.
+fn write_action<T>(action: &Action<T>, w: &mut impl core::fmt::Write) -> core::fmt::Result {
+    use crate::action::Action::*;
+    match action {
+        NoOp => w.write_char('-'),
+        Trans => w.write_char('_'),
+        KeyCode(kc) | WeakKeyCode(kc) | RepeatKey { key: kc, .. } => write!(w, "{:?}", kc),
+        OneShot(kc) | LatchKeyCode(kc) => write!(w, "{:?}", kc),
+        MultipleKeyCodes(kcs) => {
+            for (i, kc) in kcs.iter().enumerate() {
+                if i > 0 {
+                    w.write_char('+')?;
+                }
+                write!(w, "{:?}", kc)?;
+            }
+            Ok(())
+        }
+        Layer(value)
+        | LayerWithFallback { layer: value, .. }
+        | StickyLayer { layer: value, .. } => write!(w, "L{}", value),
+        DefaultLayer(value) => write!(w, "D{}", value),
+        HoldTap { tap, hold, .. } | TapDanceHold { tap, hold, .. } => {
+            write_action(tap, w)?;
+            w.write_char('/')?;
+            write_action(hold, w)
+        }
+        DoubleTapLock { inner, .. } => write_action(inner, w),
+        Custom(_) | CustomPulse { .. } | CustomOrKey { .. } | CustomCycle(_) => w.write_char('C'),
+        _ => w.write_char('?'),
+    }
+}
+
+/// Returns `true` if performing the action creates a waiting state,
+/// i.e. if it is or contains a hold-tap.
+fn waits<T>(action: &Action<T>) -> bool {
+    match action {
+        Action::MultipleActions(v) => v.iter().any(waits),
+        action => action.is_hold_tap(),
+    }
 }
 
 #[derive(Debug)]
 struct WaitingState<T: 'static> {
     coord: (u8, u8),
+    /// Remaining ticks before the timeout.
     timeout: u16,
+    /// Number of ticks the press event was stacked before being
+    /// processed, i.e. the `since` of the press event when
+    /// unstacked. The release event is stacked after the press
+    /// event, thus its `since` is usually smaller than `delay`, and
+    /// `delay - since` is the time the key was held before being
+    /// processed. As `since` saturates, and as a press and its
+    /// release can be stacked during the same tick, `since` may
+    /// exceed `delay`: the difference must be saturating.
     delay: u16,
     hold: &'static Action<T>,
     tap: &'static Action<T>,
+    /// The double tap action of a `TapDanceHold`, `None` for a
+    /// `HoldTap`.
+    double: Option<&'static Action<T>>,
     config: HoldTapConfig,
+    /// The action replacing `hold` when the hold is caused by the
+    /// timeout.
+    on_timeout: Option<&'static Action<T>>,
+    /// `true` for an auto-shifted key: the hold adds `LShift` to the
+    /// tap action.
+    auto_shift: bool,
+}
+impl<T> Clone for WaitingState<T> {
+    fn clone(&self) -> Self {
+        Self {
+            coord: self.coord,
+            timeout: self.timeout,
+            delay: self.delay,
+            hold: self.hold,
+            tap: self.tap,
+            double: self.double,
+            config: self.config,
+            on_timeout: self.on_timeout,
+            auto_shift: self.auto_shift,
+        }
+    }
+}
+enum ComboCheck {
+    /// A combo may still be completed.
+    Wait,
+    /// The combo with this index is complete.
+    Fire(usize),
 }
 enum WaitingAction {
     Hold,
+    /// A hold caused by the timeout.
+    Timeout,
     Tap,
+    DoubleTap,
     NoOp,
 }
 impl<T> WaitingState<T> {
-    fn tick(&mut self, stacked: &Deque) -> WaitingAction {
+    /// `claimed` returns `true` for the keys claimed by a combo: their
+    /// presses are ignored by the configurations reacting to the
+    /// interleaved presses.
+    fn tick(
+        &mut self,
+        stacked: &Deque,
+        hand_map: Option<fn((u8, u8)) -> Hand>,
+        claimed: impl Fn((u8, u8)) -> bool,
+    ) -> WaitingAction {
         self.timeout = self.timeout.saturating_sub(1);
+        if self.double.is_some() {
+            return self.tap_dance_tick(stacked);
+        }
+        let is_press = |s: &&Stacked| s.event.is_press() && !claimed(s.event.coord());
         match self.config {
-            HoldTapConfig::Default => (),
+            HoldTapConfig::Default | HoldTapConfig::TapPreferred => (),
             HoldTapConfig::HoldOnOtherKeyPress => {
-                if stacked.iter().any(|s| s.event.is_press()) {
+                if stacked.iter().any(|s| is_press(&s)) {
                     return WaitingAction::Hold;
                 }
             }
+            HoldTapConfig::ChordalHold => {
+                if let Some(s) = stacked.iter().find(is_press) {
+                    let same_hand = match hand_map {
+                        Some(hand) => hand(s.event.coord()) == hand(self.coord),
+                        None => false,
+                    };
+                    return if same_hand {
+                        WaitingAction::Tap
+                    } else {
+                        WaitingAction::Hold
+                    };
+                }
+            }
             HoldTapConfig::PermissiveHold => {
                 for (x, s) in stacked.iter().enumerate() {
-                    if s.event.is_press() {
+                    if is_press(&s) {
                         let (i, j) = s.event.coord();
                         let target = Event::Release(i, j);
                         if stacked.iter().skip(x + 1).any(|s| s.event == target) {
@@ -248,25 +773,73 @@ impl<T> WaitingState<T> {
             .iter()
             .find(|s| self.is_corresponding_release(&s.event))
         {
-            if self.timeout >= self.delay - since {
+            if self.timeout >= self.delay.saturating_sub(since) {
                 WaitingAction::Tap
             } else {
                 WaitingAction::Hold
             }
         } else if self.timeout == 0 {
-            WaitingAction::Hold
+            WaitingAction::Timeout
         } else {
             WaitingAction::NoOp
         }
     }
+    fn tap_dance_tick(&self, stacked: &Deque) -> WaitingAction {
+        let release = stacked
+            .iter()
+            .position(|s| self.is_corresponding_release(&s.event));
+        match release {
+            Some(x) => {
+                // the presses stacked since the release
+                let window = stacked[x].since;
+                if count_presses(stacked, self.coord, window) > 0 {
+                    WaitingAction::DoubleTap
+                } else if self.timeout == 0 {
+                    WaitingAction::Tap
+                } else {
+                    WaitingAction::NoOp
+                }
+            }
+            None if self.timeout == 0 => WaitingAction::Hold,
+            None => WaitingAction::NoOp,
+        }
+    }
     fn is_corresponding_release(&self, event: &Event) -> bool {
         matches!(event, Event::Release(i, j) if (*i, *j) == self.coord)
     }
+    fn is_corresponding_press(&self, event: &Event) -> bool {
+        matches!(event, Event::Press(i, j) if (*i, *j) == self.coord)
+    }
 }
 
-#[derive(Debug)]
+/// The double tap tracking of an `Action::DoubleTapLock` key.
+#[derive(Debug, Clone, Copy)]
+struct TapLock {
+    coord: (u8, u8),
+    timeout: u16,
+    /// Ticks since the release, `None` while pressed or after a
+    /// double tap.
+    released: Option<u16>,
+    /// The inner action stays active after the release.
+    locked: bool,
+    /// The current press is the second tap of a double tap, toggling
+    /// the lock on release.
+    toggling: bool,
+}
+
+/// A running `Action::Macro`.
+#[derive(Debug, Clone)]
+struct MacroPlayer {
+    steps: &'static [MacroStep],
+    /// The key code of a `MacroStep::Tap` to release at the next tick.
+    tapped: Option<KeyCode>,
+}
+
+#[derive(Debug, Clone)]
 struct Stacked {
     event: Event,
+    /// Number of ticks since the event was stacked. It saturates at
+    /// `u16::MAX` (about 65 seconds with a 1 ms tick).
     since: u16,
 }
 impl From<Event> for Stacked {
@@ -280,25 +853,299 @@ impl Stacked {
     }
 }
 
+/// Counts the presses of the key at `coord` stacked during the last
+/// `window` ticks, i.e. whose `since` is at most `window`. As `since`
+/// saturates and the deque is bounded, the count can't overflow, and
+/// the timing is the one of the hold-taps.
+fn count_presses(deque: &Deque, coord: (u8, u8), window: u16) -> usize {
+    let press = Event::press(coord);
+    deque
+        .iter()
+        .filter(|s| s.event == press && s.since <= window)
+        .count()
+}
+
+/// Returns the progress in the unlock `sequence` after the press of
+/// `coord`, `progress` keys being already matched: the length of the
+/// longest start of the sequence ending the matched keys followed by
+/// `coord`. Thus, a wrong press doesn't lose a restart in progress,
+/// e.g. `a a b` is unlocked by `a a a b`.
+fn unlock_progress(sequence: &[(u8, u8)], progress: usize, coord: (u8, u8)) -> usize {
+    (1..=progress + 1)
+        .rev()
+        .find(|&k| {
+            sequence[k - 1] == coord && sequence[..k - 1] == sequence[progress + 1 - k..progress]
+        })
+        .unwrap_or(0)
+}
+
+/// The keys pressed together with the first stacked event, i.e.
+/// before any release and less than `term` ticks after it.
+fn combo_presses(deque: &Deque, term: u16) -> impl Iterator<Item = (u8, u8)> + '_ {
+    let since = deque.front().map_or(0, |s| s.since);
+    deque
+        .iter()
+        .take_while(move |s| s.event.is_press() && since - s.since < term)
+        .map(|s| s.event.coord())
+}
+
+/// Returns `true` if the combo of `keys` may still be completed: the
+/// keys pressed together with the first stacked event all belong to
+/// it.
+fn combo_pending(deque: &Deque, term: u16, keys: &[(u8, u8)]) -> bool {
+    let mut pressed = combo_presses(deque, term).peekable();
+    pressed.peek().is_some() && pressed.all(|p| keys.contains(&p))
+}
+
 impl<T: 'static, const C: usize, const R: usize, const L: usize> Layout<T, C, R, L> {
     /// Creates a new `Layout` object.
     pub fn new(layers: &'static [[[Action<T>; C]; R]; L]) -> Self {
+        Self::with_history(layers)
+    }
+}
+
+impl<T: 'static, const C: usize, const R: usize, const L: usize, const H: usize>
+    Layout<T, C, R, L, H>
+{
+    /// Creates a new `Layout` object keeping the last `H` registered
+    /// events.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use keyberon::action::k;
+    /// # use keyberon::key_code::KeyCode::*;
+    /// # use keyberon::layout::{Event, Layers, Layout, NoCustom};
+    /// # static LAYERS: Layers<NoCustom, 1, 1, 1> = [[[k(A)]]];
+    /// let mut layout: Layout<_, 1, 1, 1, 2> = Layout::with_history(&LAYERS);
+    /// layout.event(Event::Press(0, 0));
+    /// layout.event(Event::Release(0, 0));
+    /// layout.event(Event::Press(0, 0));
+    /// assert!(layout
+    ///     .recent_events()
+    ///     .eq([Event::Release(0, 0), Event::Press(0, 0)].iter().copied()));
+    /// ```
+    pub fn with_history(layers: &'static [[[Action<T>; C]; R]; L]) -> Self {
         Self {
             layers,
             default_layer: 0,
             states: Vec::new(),
             waiting: None,
             deque: ArrayDeque::new(),
+            custom_events: ArrayDeque::new(),
+            keycode_remap: None,
+            holdtap_enabled: true,
+            require_prior_idle: 0,
+            last_press: u16::MAX,
+            last_keycode: None,
+            last_mods: 0,
+            min_press_ticks: 0,
+            macro_player: None,
+            tri_layer: None,
+            layer_overflow: LayerOverflowPolicy::Ignore,
+            cosmetic_layers: None,
+            auto_shift: None,
+            hand_map: None,
+            custom_predicate: None,
+            combos: &[],
+            combo_term: 0,
+            active_combos: Vec::new(),
+            default_actions: [&Action::NoOp; L],
+            key_overrides: &[],
+            forced_mods: 0,
+            modifier_timeout: None,
+            inactive_ticks: 0,
+            stuck_threshold: None,
+            stuck_hook: None,
+            paused: false,
+            lock: None,
+            pressed: Vec::new(),
+            unmatched_releases: 0,
+            cycles: Vec::new(),
+            tap_locks: Vec::new(),
+            history: [None; H],
+            #[cfg(feature = "holdtap-stats")]
+            holdtap_stats: HoldTapStats::default(),
         }
     }
     /// Iterates on the key codes of the current state.
+    ///
+    /// The key overrides are applied (see `set_key_overrides`), and
+    /// then the key codes are translated by the remapping table, if
+    /// any (see `set_keycode_remap`). Finally, the forced modifiers
+    /// that are not pressed are added (see `set_forced_modifiers`).
     pub fn keycodes(&self) -> impl Iterator<Item = KeyCode> + '_ {
-        self.states.iter().filter_map(State::keycode)
+        self.keycodes_weak().map(|(kc, _)| kc)
+    }
+    /// Iterates on the keys holding an active state: a key code, a
+    /// layer or a custom action. Each key is yielded once. Useful to
+    /// light the held keys with a per-key LED.
+    ///
+    /// The keys whose action is over, such as the tap of a hold-tap,
+    /// or a key whose key code is still active as a one shot, are
+    /// not yielded.
+    pub fn active_coords(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        let states = &self.states;
+        states
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| Some((i, s.coord()?)))
+            .filter(move |&(i, c)| !states[..i].iter().any(|s| s.coord() == Some(c)))
+            .map(|(_, c)| c)
+    }
+    /// Iterates on the key codes of the current state, as `keycodes`,
+    /// with `true` for the weak key codes.
+    fn keycodes_weak(&self) -> impl Iterator<Item = (KeyCode, bool)> + '_ {
+        let states = || self.states.iter().filter_map(State::keycode_weak);
+        let mods = states().fold(0, |mods, (kc, _)| mods | kc.as_modifier_bit());
+        let active = self
+            .key_overrides
+            .iter()
+            .find(|o| o.with_mods & mods == o.with_mods && states().any(|(kc, _)| kc == o.trigger));
+        states()
+            .filter(move |&(kc, _)| match active {
+                Some(o) => kc != o.trigger && kc.as_modifier_bit() & o.suppressed_mods == 0,
+                None => true,
+            })
+            .chain(
+                active
+                    .into_iter()
+                    .flat_map(|o| o.replacement.key_codes().map(|kc| (kc, false))),
+            )
+            .map(move |(kc, weak)| match self.keycode_remap {
+                Some(remap) if remap[kc as usize] != KeyCode::No => (remap[kc as usize], weak),
+                _ => (kc, weak),
+            })
+            .chain(
+                MODIFIERS
+                    .iter()
+                    .filter(move |kc| self.forced_mods & !mods & kc.as_modifier_bit() != 0)
+                    .map(|&kc| (kc, false)),
+            )
+    }
+    /// Builds the keyboard report of the current state, from
+    /// `keycodes`. The weak key codes (see `Action::WeakKeyCode`) are
+    /// added last, only in the slots left by the other key codes.
+    pub fn hid_report(&self) -> KbHidReport {
+        let mut report = KbHidReport::default();
+        for (kc, _) in self.keycodes_weak().filter(|&(_, weak)| !weak) {
+            report.pressed(kc);
+        }
+        for (kc, _) in self.keycodes_weak().filter(|&(_, weak)| weak) {
+            report.pressed_weak(kc);
+        }
+        report
+    }
+    /// Returns `true` if `keycodes` contains more than 6 non modifier
+    /// key codes, i.e. more than what a boot keyboard report can
+    /// send. Useful to warn the user, for example with a LED.
+    pub fn rollover_exceeded(&self) -> bool {
+        self.keycodes()
+            .filter(|kc| !kc.is_modifier())
+            .nth(6)
+            .is_some()
+    }
+    /// Returns the last key code pressed, modifiers excluded (as
+    /// repeated by `Action::Repeat`), or `None` if no key code was
+    /// pressed yet.
+    pub fn last_keycode(&self) -> Option<KeyCode> {
+        self.last_keycode
+    }
+    /// Sets the modifiers always present in the key codes, using the
+    /// USB HID modifier bitfield (see `KeyCode::as_modifier_bit`).
+    /// For example, `LShift.as_modifier_bit()` forces `LShift` for a
+    /// "gaming mode". Setting 0 disables it. These modifiers are
+    /// ignored by the key overrides.
+    pub fn set_forced_modifiers(&mut self, mods: u8) {
+        self.forced_mods = mods;
+    }
+    /// Sets the modifier timeout, in ticks: when no event occurred
+    /// for this duration while only modifiers are pressed, these
+    /// modifiers are released. A safety net against a modifier stuck
+    /// because its release was lost (by a flaky split link for
+    /// example). Disabled (`None`) by default.
+    ///
+    /// Only the modifiers pressed by a key of the matrix are
+    /// released, not the ones of `press_keycode`.
+    pub fn set_modifier_timeout(&mut self, timeout: Option<u16>) {
+        self.modifier_timeout = timeout;
+    }
+    /// Sets the stuck threshold, in ticks: when a key code has been
+    /// pressed by a key of the matrix for this duration, the stuck
+    /// hook is called once with the key and the key code. A watchdog
+    /// against a firmware bug or a short circuit: the key code is not
+    /// released. Disabled (`None`) by default.
+    pub fn set_stuck_threshold(&mut self, threshold: Option<u16>) {
+        self.stuck_threshold = threshold;
+    }
+    /// Sets the function called when a key is held for longer than
+    /// the stuck threshold, see `set_stuck_threshold`.
+    pub fn set_stuck_hook(&mut self, hook: fn((u8, u8), KeyCode)) {
+        self.stuck_hook = Some(hook);
+    }
+    /// Sets the key overrides of the layout. If several overrides
+    /// match, the first one is applied.
+    pub fn set_key_overrides(&mut self, overrides: &'static [KeyOverride<T>]) {
+        self.key_overrides = overrides;
+    }
+    /// Sets the table translating the key codes returned by
+    /// `keycodes`, or removes it with `None`.
+    ///
+    /// The table is indexed by the key code value (`kc as usize`).
+    /// An entry set to `KeyCode::No` keeps the key code unchanged,
+    /// thus only the remapped key codes need to be set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use keyberon::key_code::KeyCode::{self, *};
+    ///
+    /// // QWERTZ: swap Y and Z
+    /// static QWERTZ: [KeyCode; 256] = {
+    ///     let mut remap = [No; 256];
+    ///     remap[Y as usize] = Z;
+    ///     remap[Z as usize] = Y;
+    ///     remap
+    /// };
+    /// # use keyberon::layout::{Layers, Layout, NoCustom};
+    /// # static LAYERS: Layers<NoCustom, 1, 1, 1> = [[[keyberon::action::k(A)]]];
+    /// # let mut layout = Layout::new(&LAYERS);
+    /// layout.set_keycode_remap(Some(&QWERTZ));
+    /// ```
+    pub fn set_keycode_remap(&mut self, remap: Option<&'static [KeyCode; 256]>) {
+        self.keycode_remap = remap;
+    }
+    /// Returns the coordinates of the key waiting for a hold-tap
+    /// resolution (`HoldTap` or `TapDanceHold`), if any.
+    pub fn waiting_coord(&self) -> Option<(u8, u8)> {
+        self.waiting.as_ref().map(|w| w.coord)
+    }
+    /// Returns the tap and hold actions, in this order, of the key
+    /// waiting for a hold-tap resolution, if any.
+    pub fn waiting_actions(&self) -> Option<(&'static Action<T>, &'static Action<T>)> {
+        self.waiting.as_ref().map(|w| (w.tap, w.hold))
+    }
+    /// Returns the statistics on the `HoldTap` resolutions since the
+    /// creation of the layout.
+    #[cfg(feature = "holdtap-stats")]
+    pub fn holdtap_stats(&self) -> HoldTapStats {
+        self.holdtap_stats
     }
     fn waiting_into_hold(&mut self) -> CustomEvent<T> {
         if let Some(w) = &self.waiting {
             let hold = w.hold;
             let coord = w.coord;
+            #[cfg(feature = "holdtap-stats")]
+            if !w.auto_shift {
+                let stats = &mut self.holdtap_stats;
+                stats.holds = stats.holds.saturating_add(1);
+                if w.timeout == 0 {
+                    stats.timeouts = stats.timeouts.saturating_add(1);
+                }
+            }
+            if w.auto_shift {
+                self.push_normal_key(coord, KeyCode::LShift);
+            }
             self.waiting = None;
             self.do_action(hold, coord, 0)
         } else {
@@ -309,12 +1156,91 @@ impl<T: 'static, const C: usize, const R: usize, const L: usize> Layout<T, C, R,
         if let Some(w) = &self.waiting {
             let tap = w.tap;
             let coord = w.coord;
+            #[cfg(feature = "holdtap-stats")]
+            if !w.auto_shift {
+                self.holdtap_stats.taps = self.holdtap_stats.taps.saturating_add(1);
+            }
             self.waiting = None;
             self.do_action(tap, coord, 0)
         } else {
             CustomEvent::NoEvent
         }
     }
+    fn waiting_into_double_tap(&mut self) -> CustomEvent<T> {
+        if let Some(w) = &self.waiting {
+            let double = w.double.unwrap_or(w.tap);
+            let coord = w.coord;
+            // The first release and the second press are consumed:
+            // the double tap action is released with the second
+            // release.
+            let release = self
+                .deque
+                .iter()
+                .position(|s| w.is_corresponding_release(&s.event));
+            if let Some(x) = release {
+                self.deque.remove(x);
+                let press = self
+                    .deque
+                    .iter()
+                    .skip(x)
+                    .position(|s| w.is_corresponding_press(&s.event));
+                if let Some(y) = press {
+                    self.deque.remove(x + y);
+                }
+            }
+            self.waiting = None;
+            self.do_action(double, coord, 0)
+        } else {
+            CustomEvent::NoEvent
+        }
+    }
+    /// Returns the number of columns of the layers, i.e. `C`.
+    pub const fn cols(&self) -> usize {
+        C
+    }
+    /// Returns the number of rows of the layers, i.e. `R`.
+    pub const fn rows(&self) -> usize {
+        R
+    }
+    /// Returns the number of layers, i.e. `L`.
+    pub const fn layers(&self) -> usize {
+        L
+    }
+    /// Writes the actions of a layer, for an on-device dump of the
+    /// layout: one line per row, with the actions separated by
+    /// spaces. The `Trans` actions are resolved with the default
+    /// layer. Writes nothing if the layer doesn't exist.
+    ///
+    /// The actions are written compactly: the key codes by their
+    /// name, `A+B` for several key codes, `tap/hold` for a hold-tap,
+    /// `L1` for a layer, `D1` for a default layer, `-` for `NoOp`,
+    /// `C` for a custom action and `?` for the other actions.
+    pub fn write_layer(&self, layer: usize, w: &mut impl core::fmt::Write) -> core::fmt::Result {
+        if layer >= L {
+            return Ok(());
+        }
+        for i in 0..R {
+            for j in 0..C {
+                if j > 0 {
+                    w.write_char(' ')?;
+                }
+                write_action(self.layer_action((i as u8, j as u8), layer, false), w)?;
+            }
+            w.write_char('\n')?;
+        }
+        Ok(())
+    }
+    /// Returns `true` if the layout is idle: no key is pressed, and no
+    /// event or macro is waiting to be processed. In this case,
+    /// `tick` does nothing, and can be skipped (for example to save
+    /// power on a wireless keyboard) until the next event.
+    pub fn is_idle(&self) -> bool {
+        self.states.is_empty()
+            && self.tap_locks.is_empty()
+            && self.waiting.is_none()
+            && self.deque.is_empty()
+            && self.macro_player.is_none()
+    }
     /// A time event.
     ///
     /// This method must be called regularly, typically every millisecond.
@@ -322,80 +1248,558 @@ impl<T: 'static, const C: usize, const R: usize, const L: usize> Layout<T, C, R,
     /// Returns the corresponding `CustomEvent`, allowing to manage
     /// custom actions thanks to the `Action::Custom` variant.
     pub fn tick(&mut self) -> CustomEvent<T> {
-        //self.states = self.states.iter().filter_map(State::tick).collect();
+        self.last_press = self.last_press.saturating_add(1);
+        if self.paused || self.is_idle() {
+            return CustomEvent::NoEvent;
+        }
+        self.states.iter_mut().for_each(State::tick);
         self.deque.iter_mut().for_each(Stacked::tick);
-        match &mut self.waiting {
-            Some(w) => match w.tick(&self.deque) {
-                WaitingAction::Hold => self.waiting_into_hold(),
-                WaitingAction::Tap => self.waiting_into_tap(),
-                WaitingAction::NoOp => CustomEvent::NoEvent,
-            },
-            None => match self.deque.pop_front() {
-                Some(s) => self.unstack(s),
-                None => CustomEvent::NoEvent,
-            },
+        self.tap_locks_tick();
+        self.signal_stuck_keys();
+        self.release_stuck_modifiers();
+        self.macro_step();
+        match self.tick_events() {
+            CustomEvent::NoEvent => self.release_custom_pulse(),
+            custom => custom,
         }
     }
-    fn unstack(&mut self, stacked: Stacked) -> CustomEvent<T> {
-        use Event::*;
-        match stacked.event {
-            Release(i, j) => {
-                let mut custom = CustomEvent::NoEvent;
-                //self.states = self
-                //    .states
-                //    .iter()
-                //    .filter_map(|s| s.release((i, j), &mut custom))
-                //    .collect();
-                self.states.map_retain(|s| s.release((i, j), &mut custom));
-                custom
+    /// Advances `n` ticks, for example when the main loop fell
+    /// behind. Each tick is processed as by `tick`, so that the
+    /// hold-taps are resolved at the right time, with the events
+    /// stacked at that time.
+    ///
+    /// Stops at the first tick returning a custom event. Returns the
+    /// number of ticks processed, and the custom event.
+    pub fn tick_n(&mut self, n: u16) -> (u16, CustomEvent<T>) {
+        for i in 1..=n {
+            match self.tick() {
+                CustomEvent::NoEvent => (),
+                custom => return (i, custom),
             }
-            Press(i, j) => {
-                let action = self.press_as_action((i, j), self.current_layer());
-                self.do_action(action, (i, j), stacked.since)
+        }
+        (n, CustomEvent::NoEvent)
+    }
+    /// Counts the ticks since the press of the key codes, calling the
+    /// stuck hook when a key of the matrix reaches the stuck
+    /// threshold.
+    fn signal_stuck_keys(&mut self) {
+        for s in self.states.iter_mut() {
+            if let NormalKey {
+                keycode,
+                coord,
+                held,
+            } = s
+            {
+                let before = *held;
+                *held = held.saturating_add(1);
+                let stuck = match self.stuck_threshold {
+                    Some(threshold) => before < threshold && *held >= threshold,
+                    None => false,
+                };
+                if stuck && *coord != MACRO_COORD && *coord != INJECTED_COORD {
+                    if let Some(hook) = self.stuck_hook {
+                        hook(*coord, *keycode);
+                    }
+                }
             }
         }
     }
-    /// Register a key event.
-    pub fn event(&mut self, event: Event) {
-        if let Some(stacked) = self.deque.push_back(event.into()) {
-            self.waiting_into_hold();
-            self.unstack(stacked);
+    /// Releases the modifiers pressed alone without any event for
+    /// the modifier timeout, see `set_modifier_timeout`.
+    fn release_stuck_modifiers(&mut self) {
+        self.inactive_ticks = self.inactive_ticks.saturating_add(1);
+        match self.modifier_timeout {
+            Some(timeout) if self.inactive_ticks >= timeout => (),
+            _ => return,
         }
+        if self.keycodes().any(|kc| !kc.is_modifier()) {
+            return;
+        }
+        self.states.map_retain(|s| match *s {
+            NormalKey { keycode, coord, .. }
+                if keycode.is_modifier() && coord != INJECTED_COORD =>
+            {
+                None
+            }
+            _ => Some(*s),
+        });
     }
-    fn press_as_action(&self, coord: (u8, u8), layer: usize) -> &'static Action<T> {
-        use crate::action::Action::*;
-        let action = self
-            .layers
-            .get(layer)
-            .and_then(|l| l.get(coord.0 as usize))
-            .and_then(|l| l.get(coord.1 as usize));
-        match action {
-            None => &NoOp,
-            Some(Trans) => {
-                if layer != self.default_layer {
-                    self.press_as_action(coord, self.default_layer)
-                } else {
-                    &NoOp
+    /// Counts the ticks since the release of the `DoubleTapLock` keys,
+    /// forgetting the ones that can't be double tapped anymore.
+    fn tap_locks_tick(&mut self) {
+        for t in self.tap_locks.iter_mut() {
+            t.released = t.released.map(|r| r.saturating_add(1));
+        }
+        self.tap_locks.retain(|t| match t.released {
+            Some(r) => t.locked || r < t.timeout,
+            None => true,
+        });
+    }
+    /// Tracks the release of a `DoubleTapLock` key. Returns `true` if
+    /// the release must be ignored, the inner action being locked.
+    fn release_tap_lock(&mut self, coord: (u8, u8)) -> bool {
+        let idx = match self.tap_locks.iter().position(|t| t.coord == coord) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        let t = &mut self.tap_locks[idx];
+        if t.toggling {
+            t.toggling = false;
+            t.locked = !t.locked;
+            if !t.locked {
+                self.tap_locks.swap_remove(idx);
+            }
+            // a double tap can't start another one
+            return self.tap_locks.iter().any(|t| t.coord == coord);
+        }
+        t.released = Some(0);
+        t.locked
+    }
+    /// Resolves the waiting hold-tap, or processes the next stacked
+    /// event.
+    fn tick_events(&mut self) -> CustomEvent<T> {
+        // The keys of a combo that is complete or may still be
+        // completed are claimed by the combo: they don't resolve the
+        // waiting hold-tap, the combo is performed after it.
+        let combo = match self.waiting {
+            Some(_) => self.check_combos(),
+            None => None,
+        };
+        let (combos, deque, term) = (self.combos, &self.deque, self.combo_term);
+        let claimed = |coord| match combo {
+            Some(ComboCheck::Fire(idx)) => combos[idx].keys.contains(&coord),
+            Some(ComboCheck::Wait) => combos
+                .iter()
+                .any(|c| c.keys.contains(&coord) && combo_pending(deque, term, c.keys)),
+            None => false,
+        };
+        match &mut self.waiting {
+            Some(w) => match w.tick(&self.deque, self.hand_map, claimed) {
+                WaitingAction::Hold => self.waiting_into_hold(),
+                WaitingAction::Timeout => {
+                    if let Some(on_timeout) = w.on_timeout {
+                        w.hold = on_timeout;
+                    }
+                    self.waiting_into_hold()
+                }
+                WaitingAction::Tap => self.waiting_into_tap(),
+                WaitingAction::DoubleTap => self.waiting_into_double_tap(),
+                WaitingAction::NoOp => CustomEvent::NoEvent,
+            },
+            None => {
+                if self.short_press_pending() {
+                    return CustomEvent::NoEvent;
+                }
+                match self.check_combos() {
+                    Some(ComboCheck::Wait) => return CustomEvent::NoEvent,
+                    Some(ComboCheck::Fire(idx)) => return self.fire_combo(idx),
+                    None => (),
+                }
+                match self.deque.pop_front() {
+                    Some(s) => self.unstack(s),
+                    None => CustomEvent::NoEvent,
                 }
             }
-            Some(action) => action,
         }
     }
-    fn do_action(
-        &mut self,
+    /// Releases a `CustomPulse` that has expired. Only one custom
+    /// event can be returned per tick: the release is delayed to the
+    /// next tick without any other custom event.
+    fn release_custom_pulse(&mut self) -> CustomEvent<T> {
+        let expired = self
+            .states
+            .iter()
+            .position(|s| matches!(s, State::CustomPulse { remaining: 0, .. }));
+        match expired.map(|i| self.states.swap_remove(i)) {
+            Some(State::CustomPulse { value, .. }) => self.record(CustomEvent::Release(value)),
+            _ => CustomEvent::NoEvent,
+        }
+    }
+    /// Keeps a custom event for `take_custom_events`, and returns it.
+    fn record(&mut self, custom: CustomEvent<T>) -> CustomEvent<T> {
+        let _ = self.custom_events.push_back(custom);
+        custom
+    }
+    /// Drains the custom events produced since the last call, from
+    /// the oldest to the most recent.
+    ///
+    /// Unlike the events returned by `tick`, several custom events
+    /// produced during the same tick are all kept. At most 16 events
+    /// are kept: the oldest ones are dropped.
+    pub fn take_custom_events(&mut self) -> impl Iterator<Item = CustomEvent<T>> + '_ {
+        self.custom_events.drain(..)
+    }
+    /// Checks if the first stacked event is the press of a combo key.
+    fn check_combos(&self) -> Option<ComboCheck> {
+        let (first, since) = match self.deque.front() {
+            Some(&Stacked {
+                event: Event::Press(i, j),
+                since,
+            }) if !self.combos.is_empty() => ((i, j), since),
+            _ => return None,
+        };
+        // The keys pressed together are counted from the first one,
+        // see `combo_presses`. The processing of the first key may
+        // have been delayed (by a waiting hold-tap for example): its
+        // `since` may be greater than `combo_term`.
+        let term = self.combo_term;
+        let mut candidates = self
+            .combos
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.keys.contains(&first));
+        if let Some((idx, _)) = candidates.clone().find(|(_, c)| {
+            c.keys
+                .iter()
+                .all(|k| combo_presses(&self.deque, term).any(|p| p == *k))
+        }) {
+            return Some(ComboCheck::Fire(idx));
+        }
+        let all_pressed = self.deque.iter().all(|s| s.event.is_press());
+        if since < self.combo_term
+            && all_pressed
+            && candidates.any(|(_, c)| combo_pending(&self.deque, term, c.keys))
+        {
+            Some(ComboCheck::Wait)
+        } else {
+            None
+        }
+    }
+    fn fire_combo(&mut self, idx: usize) -> CustomEvent<T> {
+        let combo = &self.combos[idx];
+        let since = self.deque.front().map_or(0, |s| s.since);
+        for key in combo.keys {
+            let press = Event::Press(key.0, key.1);
+            if let Some(x) = self.deque.iter().position(|s| s.event == press) {
+                self.deque.remove(x);
+            }
+        }
+        let coord = combo.keys[0];
+        let action = match combo.action {
+            Action::Trans => self.press_as_action(coord, self.key_layer()),
+            action => action,
+        };
+        if !self.active_combos.contains(&idx) {
+            let _ = self.active_combos.push(idx);
+        }
+        self.do_action(action, coord, since)
+    }
+    /// Plays the next step of the running macro, if any. When the
+    /// macro is finished, the key codes it still holds are released.
+    fn macro_step(&mut self) {
+        let player = match &mut self.macro_player {
+            Some(p) => p,
+            None => return,
+        };
+        if let Some(keycode) = player.tapped.take() {
+            self.release_macro_keycode(keycode);
+            return;
+        }
+        match player.steps.split_first() {
+            Some((step, steps)) => {
+                player.steps = steps;
+                match *step {
+                    MacroStep::Press(keycode) => self.press_macro_keycode(keycode),
+                    MacroStep::Release(keycode) => self.release_macro_keycode(keycode),
+                    MacroStep::Tap(keycode) => {
+                        player.tapped = Some(keycode);
+                        self.press_macro_keycode(keycode);
+                    }
+                }
+            }
+            None => {
+                self.macro_player = None;
+                let all = &mut self.custom_events;
+                self.states
+                    .map_retain(|s| s.release(MACRO_COORD, &mut CustomEvent::NoEvent, all));
+            }
+        }
+    }
+    fn press_macro_keycode(&mut self, keycode: KeyCode) {
+        self.push_normal_key(MACRO_COORD, keycode);
+    }
+    /// Presses a key code, unless it is already pressed by the same
+    /// key.
+    fn push_normal_key(&mut self, coord: (u8, u8), keycode: KeyCode) {
+        if !self.states.iter().any(|s| s.is_key(coord, keycode)) {
+            let _ = self.states.push(NormalKey {
+                coord,
+                keycode,
+                held: 0,
+            });
+        }
+    }
+    fn release_macro_keycode(&mut self, keycode: KeyCode) {
+        self.states.map_retain(|s| match *s {
+            NormalKey {
+                keycode: kc, coord, ..
+            } if kc == keycode && coord == MACRO_COORD => None,
+            _ => Some(*s),
+        });
+    }
+    /// Returns `true` if the first stacked event is a press that must
+    /// not be processed yet, as it may still be released before the
+    /// minimum press duration. If it was released too early, the
+    /// press and the release are discarded.
+    fn short_press_pending(&mut self) -> bool {
+        let min = self.min_press_ticks;
+        let (coord, since) = match self.deque.front() {
+            Some(&Stacked {
+                event: Event::Press(i, j),
+                since,
+            }) if min > 0 => ((i, j), since),
+            _ => return false,
+        };
+        let release = self
+            .deque
+            .iter()
+            .enumerate()
+            .find(|(_, s)| s.event == Event::Release(coord.0, coord.1))
+            .map(|(x, s)| (x, s.since));
+        match release {
+            Some((x, release_since)) if since.saturating_sub(release_since) < min => {
+                self.deque.remove(x);
+                self.deque.pop_front();
+                true
+            }
+            Some(_) => false,
+            None => since < min,
+        }
+    }
+    fn unstack(&mut self, stacked: Stacked) -> CustomEvent<T> {
+        use Event::*;
+        match stacked.event {
+            Release(i, j) => {
+                if self.release_tap_lock((i, j)) {
+                    return CustomEvent::NoEvent;
+                }
+                let mut custom = CustomEvent::NoEvent;
+                //self.states = self
+                //    .states
+                //    .iter()
+                //    .filter_map(|s| s.release((i, j), &mut custom))
+                //    .collect();
+                let all = &mut self.custom_events;
+                self.states
+                    .map_retain(|s| s.release((i, j), &mut custom, all));
+                let combos = self.combos;
+                let mut released = None;
+                self.active_combos.retain(|&idx| {
+                    if combos[idx].keys.contains(&(i, j)) {
+                        released = Some(combos[idx].keys[0]);
+                        false
+                    } else {
+                        true
+                    }
+                });
+                if let Some(coord) = released {
+                    let all = &mut self.custom_events;
+                    self.states
+                        .map_retain(|s| s.release(coord, &mut custom, all));
+                }
+                custom
+            }
+            Press(i, j) => {
+                let action = self.press_as_action((i, j), self.key_layer());
+                let custom = match action {
+                    &Action::KeyCode(kc) if self.is_auto_shifted(kc) => {
+                        self.waiting = Some(WaitingState {
+                            coord: (i, j),
+                            timeout: self.auto_shift.map_or(0, |(timeout, _)| timeout),
+                            delay: stacked.since,
+                            hold: action,
+                            tap: action,
+                            double: None,
+                            config: HoldTapConfig::Default,
+                            on_timeout: None,
+                            auto_shift: true,
+                        });
+                        CustomEvent::NoEvent
+                    }
+                    _ => self.do_action(action, (i, j), stacked.since),
+                };
+                self.use_sticky_layers((i, j));
+                self.last_press = stacked.since;
+                custom
+            }
+        }
+    }
+    /// Iterates on the last registered events, from the oldest to
+    /// the most recent. At most `H` events are kept.
+    pub fn recent_events(&self) -> impl Iterator<Item = Event> + '_ {
+        self.history.iter().filter_map(|e| *e)
+    }
+    /// Returns the number of key releases without a corresponding
+    /// press since the creation of the layout.
+    ///
+    /// It is 0 unless some events are lost, for example on flaky
+    /// hardware or on a desynchronized link between the halves of a
+    /// split keyboard. These releases are processed as usual, i.e.
+    /// they release nothing. If more than 64 keys are pressed at the
+    /// same time, the releases of the extra keys are counted.
+    pub fn unmatched_releases(&self) -> u32 {
+        self.unmatched_releases
+    }
+    /// Iterates on the events waiting to be processed, from the
+    /// oldest to the most recent, with the number of ticks since
+    /// they were registered. Useful to diagnose a hold-tap waiting
+    /// more than expected.
+    #[cfg(feature = "test-utils")]
+    pub fn pending_events(&self) -> impl Iterator<Item = (Event, u16)> + '_ {
+        self.deque.iter().map(|s| (s.event, s.since))
+    }
+    /// Register a key event.
+    ///
+    /// Returns `true` if the events waiting to be processed overflowed.
+    /// In this case, the oldest waiting event has been processed
+    /// immediately, forcing a pending hold-tap into its hold action
+    /// (or its tap action for `HoldTapConfig::TapPreferred`), and the
+    /// corresponding custom events are lost. This means that
+    /// the layout is overloaded, and `tick` is not called often
+    /// enough.
+    ///
+    /// The events at `UNMAPPED_COORD` are ignored.
+    pub fn event(&mut self, event: Event) -> bool {
+        if event.coord() == UNMAPPED_COORD {
+            return false;
+        }
+        self.inactive_ticks = 0;
+        if H > 0 {
+            self.history.copy_within(1.., 0);
+            self.history[H - 1] = Some(event);
+        }
+        let coord = event.coord();
+        let pressed = self.pressed.iter().position(|&c| c == coord);
+        match (event, pressed) {
+            (Event::Press(..), None) => {
+                let _ = self.pressed.push(coord);
+            }
+            (Event::Press(..), Some(_)) => (),
+            (Event::Release(..), Some(idx)) => {
+                self.pressed.swap_remove(idx);
+            }
+            (Event::Release(..), None) => {
+                self.unmatched_releases = self.unmatched_releases.saturating_add(1);
+            }
+        }
+        if self.paused {
+            return false;
+        }
+        if let Some((sequence, progress)) = &mut self.lock {
+            if let Event::Press(i, j) = event {
+                *progress = unlock_progress(sequence, *progress, (i, j));
+                if *progress == sequence.len() {
+                    self.lock = None;
+                }
+            }
+            return false;
+        }
+        if let Some(stacked) = self.deque.push_back(event.into()) {
+            match &self.waiting {
+                Some(w) if w.config == HoldTapConfig::TapPreferred => self.waiting_into_tap(),
+                _ => self.waiting_into_hold(),
+            };
+            self.unstack(stacked);
+            true
+        } else {
+            false
+        }
+    }
+    /// Registers the events of a matrix scan, and then ticks.
+    ///
+    /// This method must be called regularly, typically every
+    /// millisecond, in place of `tick`, with the result of a matrix
+    /// scan (such as `DebouncedMatrix::scan`).
+    ///
+    /// Returns the `CustomEvent` of the tick.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use keyberon::action::k;
+    /// # use keyberon::key_code::KeyCode::*;
+    /// # use keyberon::layout::{Event, Layers, Layout, NoCustom};
+    /// # static LAYERS: Layers<NoCustom, 1, 1, 1> = [[[k(A)]]];
+    /// let mut layout = Layout::new(&LAYERS);
+    /// // typically `matrix.scan()?`
+    /// let scan = Some([Event::Press(0, 0)]);
+    /// layout.process_scan(scan);
+    /// assert_eq!(layout.keycodes().collect::<Vec<_>>(), &[A]);
+    /// ```
+    pub fn process_scan<I>(&mut self, events: Option<I>) -> CustomEvent<T>
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        for e in events.into_iter().flatten() {
+            self.event(e);
+        }
+        self.tick()
+    }
+    fn press_as_action(&self, coord: (u8, u8), layer: usize) -> &'static Action<T> {
+        self.layer_action(coord, layer, true)
+    }
+    /// Returns the action of `coord` on `layer`. If `follow_fallback`
+    /// is `true`, the `Trans` keys of a layer activated by an
+    /// `Action::LayerWithFallback` use its fallback layer, else the
+    /// default layer.
+    fn layer_action(
+        &self,
+        coord: (u8, u8),
+        layer: usize,
+        follow_fallback: bool,
+    ) -> &'static Action<T> {
+        use crate::action::Action::*;
+        let action = self
+            .layers
+            .get(layer)
+            .and_then(|l| l.get(coord.0 as usize))
+            .and_then(|l| l.get(coord.1 as usize));
+        let trans = || {
+            let fallback = self.states.iter().find_map(|s| match *s {
+                LayerModifier {
+                    value,
+                    fallback: Some(f),
+                    ..
+                } if follow_fallback && value == layer => Some(f),
+                _ => None,
+            });
+            self.layer_action(coord, fallback.unwrap_or(self.default_layer), false)
+        };
+        match action {
+            Some(Trans) if layer != self.default_layer => trans(),
+            None | Some(NoOp) | Some(Trans) => {
+                match self.default_actions.get(layer).copied().unwrap_or(&NoOp) {
+                    Trans if layer != self.default_layer => trans(),
+                    Trans => &NoOp,
+                    action => action,
+                }
+            }
+            Some(action) => action,
+        }
+    }
+    fn do_action(
+        &mut self,
         action: &'static Action<T>,
         coord: (u8, u8),
         delay: u16,
     ) -> CustomEvent<T> {
-        assert!(self.waiting.is_none());
         use Action::*;
         match action {
             NoOp | Trans => (),
+            // Only one hold-tap can wait for its resolution (see
+            // `MultipleActions`)
+            HoldTap { .. } | TapDanceHold { .. } if self.waiting.is_some() => (),
+            HoldTap { tap, .. } | TapDanceHold { tap, .. }
+                if !self.holdtap_enabled || self.in_typing_streak(delay) =>
+            {
+                return self.do_action(tap, coord, delay);
+            }
             HoldTap {
                 timeout,
                 hold,
                 tap,
                 config,
+                on_timeout,
                 ..
             } => {
                 let waiting: WaitingState<T> = WaitingState {
@@ -404,43 +1808,364 @@ impl<T: 'static, const C: usize, const R: usize, const L: usize> Layout<T, C, R,
                     delay,
                     hold,
                     tap,
+                    double: None,
                     config: *config,
+                    on_timeout: *on_timeout,
+                    auto_shift: false,
+                };
+                self.waiting = Some(waiting);
+            }
+            TapDanceHold {
+                timeout,
+                hold,
+                tap,
+                double,
+            } => {
+                let waiting: WaitingState<T> = WaitingState {
+                    coord,
+                    timeout: *timeout,
+                    delay,
+                    hold,
+                    tap,
+                    double: Some(double),
+                    config: HoldTapConfig::Default,
+                    on_timeout: None,
+                    auto_shift: false,
                 };
                 self.waiting = Some(waiting);
             }
             &KeyCode(keycode) => {
-                let _ = self.states.push(NormalKey { coord, keycode });
+                self.trigger_one_shots(coord, core::slice::from_ref(&keycode));
+                self.update_last_keycode(core::slice::from_ref(&keycode));
+                self.push_normal_key(coord, keycode);
             }
             &MultipleKeyCodes(v) => {
+                self.trigger_one_shots(coord, v);
+                self.update_last_keycode(v);
                 for &keycode in v {
-                    let _ = self.states.push(NormalKey { coord, keycode });
+                    self.push_normal_key(coord, keycode);
+                }
+            }
+            &WeakKeyCode(keycode) => {
+                self.trigger_one_shots(coord, core::slice::from_ref(&keycode));
+                self.update_last_keycode(core::slice::from_ref(&keycode));
+                let state = WeakKey { coord, keycode };
+                if !self.states.iter().any(|s| s.is_key(coord, keycode)) {
+                    let _ = self.states.push(state);
+                }
+            }
+            &RepeatKey {
+                key,
+                initial_delay,
+                interval,
+            } => {
+                self.trigger_one_shots(coord, core::slice::from_ref(&key));
+                self.update_last_keycode(core::slice::from_ref(&key));
+                let _ = self.states.push(State::RepeatKey {
+                    keycode: key,
+                    coord,
+                    interval,
+                    remaining: initial_delay.max(1),
+                    down: true,
+                });
+            }
+            &LatchKeyCode(keycode) => {
+                let latched = self
+                    .states
+                    .iter()
+                    .position(|s| matches!(s, Latched { keycode: kc } if *kc == keycode));
+                match latched {
+                    Some(idx) => {
+                        self.states.swap_remove(idx);
+                    }
+                    None => {
+                        let _ = self.states.push(Latched { keycode });
+                    }
+                }
+            }
+            &OneShot(keycode) => {
+                // re-tapping the key cancels the one shot
+                let armed = |s: &State<T>| match *s {
+                    State::OneShot {
+                        keycode: kc,
+                        coord: c,
+                    } => kc == keycode && c == coord,
+                    _ => false,
+                };
+                match self.states.iter().position(armed) {
+                    Some(idx) => {
+                        self.states.swap_remove(idx);
+                    }
+                    None => {
+                        let _ = self.states.push(State::OneShot { keycode, coord });
+                    }
                 }
             }
             &MultipleActions(v) => {
                 let mut custom = CustomEvent::NoEvent;
-                for action in v {
+                for action in v.iter().filter(|a| !waits(a)) {
+                    custom.update(self.do_action(action, coord, delay));
+                }
+                for action in v.iter().filter(|a| waits(a)) {
                     custom.update(self.do_action(action, coord, delay));
                 }
                 return custom;
             }
+            &Macro(steps) => {
+                if self.macro_player.is_some() {
+                    let all = &mut self.custom_events;
+                    self.states
+                        .map_retain(|s| s.release(MACRO_COORD, &mut CustomEvent::NoEvent, all));
+                }
+                self.macro_player = Some(MacroPlayer {
+                    steps,
+                    tapped: None,
+                });
+                self.macro_step();
+            }
+            Adaptive { default, rules } => {
+                let action = rules
+                    .iter()
+                    .find(|(kc, _)| Some(*kc) == self.last_keycode)
+                    .map_or(*default, |(_, action)| *action);
+                return self.do_action(action, coord, delay);
+            }
+            Repeat => {
+                if let Some(keycode) = self.last_keycode {
+                    let last_mods = self.last_mods;
+                    let mods = MODIFIERS
+                        .iter()
+                        .filter(|kc| last_mods & kc.as_modifier_bit() != 0);
+                    for &keycode in mods.chain(Some(&keycode)) {
+                        self.push_normal_key(coord, keycode);
+                    }
+                }
+            }
             &Layer(value) => {
-                let _ = self.states.push(LayerModifier { value, coord });
+                let _ = self.states.push(LayerModifier {
+                    value,
+                    fallback: None,
+                    coord,
+                });
+            }
+            &StickyLayer { layer, timeout } => {
+                let _ = self.states.push(State::StickyLayer {
+                    value: layer,
+                    coord,
+                    remaining: timeout,
+                    used: false,
+                });
+            }
+            &LayerRelative(delta) => {
+                let layer = self.key_layer() as isize + delta as isize;
+                let value = layer.max(0).min(L as isize - 1) as usize;
+                let _ = self.states.push(LayerModifier {
+                    value,
+                    fallback: None,
+                    coord,
+                });
+            }
+            &LayerWithFallback { layer, fallback } => {
+                let _ = self.states.push(LayerModifier {
+                    value: layer,
+                    fallback: Some(fallback),
+                    coord,
+                });
             }
             DefaultLayer(value) => {
                 self.set_default_layer(*value);
             }
+            &DoubleTapLock { inner, timeout } => {
+                let idx = match self.tap_locks.iter().position(|t| t.coord == coord) {
+                    Some(idx) => Some(idx),
+                    None => {
+                        let tap_lock = TapLock {
+                            coord,
+                            timeout,
+                            released: None,
+                            locked: false,
+                            toggling: false,
+                        };
+                        // a full table gives a plain momentary action
+                        self.tap_locks
+                            .push(tap_lock)
+                            .ok()
+                            .map(|()| self.tap_locks.len() - 1)
+                    }
+                };
+                let locked = match idx.map(|idx| &mut self.tap_locks[idx]) {
+                    Some(t) => {
+                        t.toggling = matches!(t.released, Some(r) if r < timeout);
+                        t.released = None;
+                        t.locked
+                    }
+                    None => false,
+                };
+                if !locked {
+                    return self.do_action(inner, coord, delay);
+                }
+            }
+            Lock { unlock_sequence } => {
+                if !unlock_sequence.is_empty() {
+                    self.lock = Some((unlock_sequence, 0));
+                    return self.flush();
+                }
+            }
             Custom(value) => {
                 if self.states.push(State::Custom { value, coord }).is_ok() {
-                    return CustomEvent::Press(value);
+                    return self.record(CustomEvent::Press(value));
+                }
+            }
+            CustomOrKey { value, key } => {
+                let custom = match self.custom_predicate {
+                    Some(predicate) => predicate(value),
+                    None => true,
+                };
+                if !custom {
+                    self.trigger_one_shots(coord, core::slice::from_ref(key));
+                    self.update_last_keycode(core::slice::from_ref(key));
+                    self.push_normal_key(coord, *key);
+                } else if self.states.push(State::Custom { value, coord }).is_ok() {
+                    return self.record(CustomEvent::Press(value));
+                }
+            }
+            CustomPulse { value, ticks } => {
+                let remaining = *ticks;
+                if self
+                    .states
+                    .push(State::CustomPulse { value, remaining })
+                    .is_ok()
+                {
+                    return self.record(CustomEvent::Press(value));
+                }
+            }
+            CustomCycle(values) => {
+                if values.is_empty() {
+                    return CustomEvent::NoEvent;
+                }
+                let i = self.next_cycle_index(coord, values.len());
+                let value = values[i];
+                if self.states.push(State::Custom { value, coord }).is_ok() {
+                    return self.record(CustomEvent::Press(value));
                 }
             }
         }
         CustomEvent::NoEvent
     }
 
+    /// Returns the position in the cycle of the `CustomCycle` at
+    /// `coord`, and advances it. Only 8 keys are tracked: the other
+    /// ones stay on their first value.
+    fn next_cycle_index(&mut self, coord: (u8, u8), len: usize) -> usize {
+        match self.cycles.iter_mut().find(|(c, _)| *c == coord) {
+            Some((_, i)) => {
+                let cur = *i % len;
+                *i = (cur + 1) % len;
+                cur
+            }
+            None => {
+                let _ = self.cycles.push((coord, 1 % len));
+                0
+            }
+        }
+    }
+
+    /// If a non modifier key code is pressed, the armed one shot key
+    /// codes are attached to the pressed key, and thus released with
+    /// it.
+    fn trigger_one_shots(&mut self, coord: (u8, u8), keycodes: &[KeyCode]) {
+        if keycodes.iter().all(|kc| kc.is_modifier()) {
+            return;
+        }
+        let mut i = 0;
+        while i < self.states.len() {
+            if let OneShot { keycode, .. } = self.states[i] {
+                if self.states.iter().any(|s| s.is_key(coord, keycode)) {
+                    self.states.swap_remove(i);
+                    continue;
+                }
+                self.states[i] = NormalKey {
+                    keycode,
+                    coord,
+                    held: 0,
+                };
+            }
+            i += 1;
+        }
+    }
+
+    /// After the press of the key at `coord`: the one shot layers are
+    /// consumed, and the held sticky layers of the other keys are
+    /// used.
+    fn use_sticky_layers(&mut self, coord: (u8, u8)) {
+        self.states.map_retain(|s| match *s {
+            OneShotLayer { .. } => None,
+            State::StickyLayer {
+                value,
+                coord: c,
+                remaining,
+                ..
+            } if c != coord => Some(State::StickyLayer {
+                value,
+                coord: c,
+                remaining,
+                used: true,
+            }),
+            _ => Some(*s),
+        });
+    }
+
+    fn update_last_keycode(&mut self, keycodes: &[KeyCode]) {
+        if let Some(&kc) = keycodes.iter().rev().find(|kc| !kc.is_modifier()) {
+            self.last_keycode = Some(kc);
+            self.last_mods = self
+                .states
+                .iter()
+                .filter_map(State::keycode)
+                .chain(keycodes.iter().copied())
+                .fold(0, |mods, kc| mods | kc.as_modifier_bit());
+        }
+    }
+
     /// Obtain the index of the current active layer
+    ///
+    /// The active layer modifiers are summed, but several keys
+    /// activating the same layer count only once: the layer stays
+    /// active until all of them are released.
+    ///
+    /// If a tri-layer is configured (see `set_tri_layer`) and its two
+    /// layers are active, its target layer is returned.
+    ///
+    /// The cosmetic layers (see `set_cosmetic_layers`) are included,
+    /// but not in the layer used to resolve the pressed keys.
     pub fn current_layer(&self) -> usize {
-        let mut iter = self.states.iter().filter_map(State::get_layer);
+        self.layer(true)
+    }
+    /// The layer used to resolve the pressed keys: the current layer
+    /// without the cosmetic layers.
+    fn key_layer(&self) -> usize {
+        self.layer(false)
+    }
+    fn layer(&self, with_cosmetic: bool) -> usize {
+        let is_cosmetic = |l: usize| match self.cosmetic_layers {
+            Some(cosmetic) => !with_cosmetic && cosmetic.get(l).copied().unwrap_or(false),
+            None => false,
+        };
+        let layers = || {
+            self.states
+                .iter()
+                .filter_map(State::get_layer)
+                .filter(move |&l| !is_cosmetic(l))
+        };
+        if let Some((lower, upper, target)) = self.tri_layer {
+            if layers().any(|l| l == lower) && layers().any(|l| l == upper) {
+                return target;
+            }
+        }
+        let mut iter = layers()
+            .enumerate()
+            .filter(|&(i, l)| !layers().take(i).any(|prev| prev == l))
+            .map(|(_, l)| l);
         let mut layer = match iter.next() {
             None => self.default_layer,
             Some(l) => l,
@@ -448,7 +2173,88 @@ impl<T: 'static, const C: usize, const R: usize, const L: usize> Layout<T, C, R,
         for l in iter {
             layer += l;
         }
-        layer
+        match self.layer_overflow {
+            LayerOverflowPolicy::Wrap if layer >= L => layer % L,
+            LayerOverflowPolicy::Clamp if layer >= L => L - 1,
+            _ => layer,
+        }
+    }
+
+    /// Sets the handling of a current layer beyond the last layer,
+    /// as the sum of several active layers. The default,
+    /// `LayerOverflowPolicy::Ignore`, keeps this nonexistent layer,
+    /// whose keys do nothing.
+    pub fn set_layer_overflow_policy(&mut self, policy: LayerOverflowPolicy) {
+        self.layer_overflow = policy;
+    }
+
+    /// Sets the cosmetic layers, `layers[i]` being `true` for a
+    /// cosmetic layer `i`. An active cosmetic layer is reported by
+    /// `current_layer` (to change the color of the LEDs for
+    /// example), but doesn't change the actions of the pressed keys:
+    /// they are resolved as if it was not active.
+    pub fn set_cosmetic_layers(&mut self, layers: &'static [bool; L]) {
+        self.cosmetic_layers = Some(layers);
+    }
+
+    /// Configures a tri-layer: while the `lower` and `upper` layers
+    /// are both activated by layer modifiers, the `target` layer is
+    /// active instead.
+    ///
+    /// This is the classic "lower + raise = adjust" layout.
+    pub fn set_tri_layer(&mut self, lower: usize, upper: usize, target: usize) {
+        self.tri_layer = Some((lower, upper, target));
+    }
+
+    /// Enables the auto-shift on the layers `layers[i]` is `true`
+    /// for: a key performing a printable key code (except `Space`)
+    /// held for more than `timeout` ticks is shifted, and it is
+    /// typed normally if released before.
+    ///
+    /// The auto-shift is resolved as a `HoldTap` with the default
+    /// configuration. Only the `KeyCode` actions of the pressed keys
+    /// are auto-shifted, not the ones of `MultipleActions`, macros,
+    /// hold-taps or combos. The keys of the disabled layers are
+    /// processed immediately.
+    pub fn set_auto_shift(&mut self, timeout: u16, layers: &'static [bool; L]) {
+        self.auto_shift = Some((timeout, layers));
+    }
+
+    fn is_auto_shifted(&self, keycode: KeyCode) -> bool {
+        match self.auto_shift {
+            Some((_, layers)) => {
+                layers.get(self.key_layer()).copied().unwrap_or(false)
+                    && keycode != KeyCode::Space
+                    && keycode.to_char(false).is_some()
+            }
+            None => false,
+        }
+    }
+
+    /// Sets the function giving the hand of the keys, used by
+    /// `HoldTapConfig::ChordalHold`.
+    pub fn set_hand_map(&mut self, hand_map: fn((u8, u8)) -> Hand) {
+        self.hand_map = Some(hand_map);
+    }
+
+    /// Sets the predicate choosing, at press time, between the custom
+    /// action and the key code of an `Action::CustomOrKey`: `true`
+    /// for the custom action.
+    pub fn set_custom_predicate(&mut self, predicate: fn(&T) -> bool) {
+        self.custom_predicate = Some(predicate);
+    }
+
+    /// Sets the combos of the layout.
+    ///
+    /// The keys of a combo must all be pressed in less than `term`
+    /// ticks, starting from the press of the first one. While a combo
+    /// may be completed, the processing of the pressed keys is
+    /// delayed. If several combos are completed, the first one in
+    /// `combos` is performed.
+    pub fn set_combos(&mut self, combos: &'static [Combo<T>], term: u16) {
+        self.combos = combos;
+        self.combo_term = term;
+        self.active_combos.clear();
     }
 
     /// Sets the default layer for the layout
@@ -457,43 +2263,300 @@ impl<T: 'static, const C: usize, const R: usize, const L: usize> Layout<T, C, R,
             self.default_layer = value
         }
     }
-}
-
-trait MapRetain<T> {
-    fn map_retain<F>(&mut self, f: F)
-    where
-        F: FnMut(&T) -> Option<T>;
-}
 
-impl<T, const S: usize> MapRetain<T> for Vec<T, { S }> {
-    fn map_retain<F>(&mut self, mut f: F)
-    where
-        F: FnMut(&T) -> Option<T>,
-    {
-        let mut processed = 0;
-        while processed < self.len() {
-            let res = f(&self[processed]);
-            match res {
-                Some(t) => {
-                    self[processed] = t;
-                    processed += 1;
+    /// Sets the default layer, releasing the held key codes that
+    /// the new layer would not press.
+    ///
+    /// A key code pressed by a key is released if the action of the
+    /// key changes with the new default layer, and if the new action
+    /// doesn't send this key code. This avoids keys stuck until their
+    /// release, or a release not matching the action of the press.
+    pub fn set_default_layer_clean(&mut self, value: usize) {
+        let key = |s: &State<T>| match *s {
+            NormalKey { keycode, .. }
+            | WeakKey { keycode, .. }
+            | State::RepeatKey { keycode, .. } => s.coord().map(|coord| (coord, keycode)),
+            _ => None,
+        };
+        let mut old: Vec<((u8, u8), &'static Action<T>), 64> = Vec::new();
+        for (coord, _) in self.states.iter().filter_map(key) {
+            let action = self.press_as_action(coord, self.key_layer());
+            let _ = old.push((coord, action));
+        }
+        self.set_default_layer(value);
+        let layer = self.key_layer();
+        let kept = |coord, keycode| match old.iter().find(|(c, _)| *c == coord) {
+            Some(&(_, action)) => {
+                let new = self.press_as_action(coord, layer);
+                core::ptr::eq(action, new) || new.key_codes().any(|kc| kc == keycode)
+            }
+            None => true,
+        };
+        let mut released: Vec<usize, 64> = Vec::new();
+        for (i, s) in self.states.iter().enumerate() {
+            if let Some((coord, keycode)) = key(s) {
+                if !kept(coord, keycode) {
+                    let _ = released.push(i);
                 }
-                None => unsafe {
-                    self.swap_remove_unchecked(processed);
-                },
             }
         }
+        for &i in released.iter().rev() {
+            self.states.swap_remove(i);
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    extern crate std;
-    use super::{Event::*, Layout, *};
-    use crate::action::Action::*;
-    use crate::action::HoldTapConfig;
-    use crate::action::{k, l, m};
-    use crate::key_code::KeyCode;
+    /// Captures the runtime state of the layout, to be restored later
+    /// by `restore`. Useful to rewind a replay debugger.
+    pub fn snapshot(&self) -> LayoutSnapshot<T> {
+        LayoutSnapshot {
+            default_layer: self.default_layer,
+            states: self.states.clone(),
+            waiting: self.waiting.clone(),
+            deque: self.deque.clone(),
+            active_combos: self.active_combos.clone(),
+            cycles: self.cycles.clone(),
+            tap_locks: self.tap_locks.clone(),
+            last_keycode: self.last_keycode,
+            last_mods: self.last_mods,
+            macro_player: self.macro_player.clone(),
+            lock: self.lock,
+            pressed: self.pressed.clone(),
+            inactive_ticks: self.inactive_ticks,
+            last_press: self.last_press,
+        }
+    }
+
+    /// Restores the runtime state captured by `snapshot`. The
+    /// snapshot must come from a layout with the same layers.
+    pub fn restore(&mut self, snap: &LayoutSnapshot<T>) {
+        self.default_layer = snap.default_layer;
+        self.states = snap.states.clone();
+        self.waiting = snap.waiting.clone();
+        self.deque = snap.deque.clone();
+        self.active_combos = snap.active_combos.clone();
+        self.cycles = snap.cycles.clone();
+        self.tap_locks = snap.tap_locks.clone();
+        self.last_keycode = snap.last_keycode;
+        self.last_mods = snap.last_mods;
+        self.macro_player = snap.macro_player.clone();
+        self.lock = snap.lock;
+        self.pressed = snap.pressed.clone();
+        self.inactive_ticks = snap.inactive_ticks;
+        self.last_press = snap.last_press;
+    }
+
+    /// Sets the action of the undefined keys of the given layer.
+    ///
+    /// A key is undefined if its coordinates are out of the layer or
+    /// if its action resolves to `NoOp`. Setting `Trans` makes the
+    /// undefined keys transparent to the default layer. Defaults to
+    /// `NoOp`.
+    pub fn set_layer_default_action(&mut self, layer: usize, action: &'static Action<T>) {
+        if let Some(a) = self.default_actions.get_mut(layer) {
+            *a = action;
+        }
+    }
+
+    /// Enables or disables the hold-tap resolution.
+    ///
+    /// When disabled, the `HoldTap` and `TapDanceHold` actions
+    /// immediately perform their tap action, as a passthrough mode
+    /// (for a typing test for example). Enabled by default.
+    pub fn set_holdtap_enabled(&mut self, enabled: bool) {
+        self.holdtap_enabled = enabled;
+    }
+
+    /// Sets the minimum idle time, in ticks, before a hold-tap press
+    /// for the hold-tap to be resolved. If another key was pressed
+    /// less than `ticks` ticks before, the typing is considered as a
+    /// streak: the hold-tap immediately performs its tap action. This
+    /// avoids accidental holds of home row mods while typing fast.
+    /// Disabled (set to 0) by default.
+    ///
+    /// The idle time is counted by `tick`, that must then be called
+    /// even when the layout is idle.
+    pub fn set_require_prior_idle(&mut self, ticks: u16) {
+        self.require_prior_idle = ticks;
+    }
+    /// Returns `true` if a hold-tap pressed `delay` ticks ago follows
+    /// another key press by less than the required prior idle time.
+    fn in_typing_streak(&self, delay: u16) -> bool {
+        self.last_press.saturating_sub(delay) < self.require_prior_idle
+    }
+
+    /// Sets the minimum duration, in ticks, of a key press.
+    ///
+    /// A key pressed and released in less than `ticks` ticks is
+    /// ignored, filtering accidental brushes. As a consequence, the
+    /// processing of every key press is delayed by `ticks` ticks.
+    /// Disabled (set to 0) by default.
+    pub fn set_min_press_ticks(&mut self, ticks: u16) {
+        self.min_press_ticks = ticks;
+    }
+
+    /// Presses the given key code, bypassing the layer lookup.
+    ///
+    /// The key code is active until the corresponding
+    /// `release_keycode` call. It is not attached to any key of the
+    /// matrix, thus releasing a key can't release it.
+    pub fn press_keycode(&mut self, keycode: KeyCode) {
+        self.push_normal_key(INJECTED_COORD, keycode);
+    }
+
+    /// Releases a key code pressed by `press_keycode`.
+    pub fn release_keycode(&mut self, keycode: KeyCode) {
+        self.states.map_retain(|s| match *s {
+            NormalKey {
+                keycode: kc, coord, ..
+            } if kc == keycode && coord == INJECTED_COORD => None,
+            _ => Some(*s),
+        });
+    }
+
+    /// Checks the consistency of the internal state, panicking if it
+    /// is broken. Only available with the debug assertions, to be
+    /// called by a fuzzing harness after each `event` or `tick`.
+    ///
+    /// The checked invariants are:
+    /// - a key code is pressed at most once by a given key,
+    /// - a key code is armed as a one shot or latched at most once,
+    /// - the default layer exists,
+    /// - the active combos exist, and are active at most once,
+    /// - the pressed keys are tracked at most once.
+    #[cfg(debug_assertions)]
+    pub fn check_invariants(&self) {
+        for (i, s) in self.states.iter().enumerate() {
+            let dup = self.states[..i].iter().any(|prev| match (*prev, *s) {
+                (
+                    NormalKey { keycode, coord, .. },
+                    NormalKey {
+                        keycode: k,
+                        coord: c,
+                        ..
+                    },
+                ) => keycode == k && coord == c,
+                (
+                    OneShot { keycode, coord },
+                    OneShot {
+                        keycode: k,
+                        coord: c,
+                    },
+                ) => keycode == k && coord == c,
+                (Latched { keycode }, Latched { keycode: k }) => keycode == k,
+                _ => false,
+            });
+            assert!(!dup, "duplicated state {:?}", s.keycode());
+        }
+        assert!(self.default_layer < L, "invalid default layer");
+        for (i, &idx) in self.active_combos.iter().enumerate() {
+            assert!(idx < self.combos.len(), "invalid active combo");
+            assert!(!self.active_combos[..i].contains(&idx), "duplicated combo");
+        }
+        for (i, c) in self.pressed.iter().enumerate() {
+            assert!(!self.pressed[..i].contains(c), "duplicated pressed key");
+        }
+    }
+
+    /// Releases all the key codes and custom actions currently
+    /// active, but keeps the layer modifiers.
+    ///
+    /// This is useful to flush the state on an external request (for
+    /// example a command from the host) without losing the current
+    /// layer. Returns the `CustomEvent` corresponding to the released
+    /// custom actions, if any.
+    pub fn clear_keys_keep_layers(&mut self) -> CustomEvent<T> {
+        let mut custom = CustomEvent::NoEvent;
+        let all = &mut self.custom_events;
+        self.states.map_retain(|s| match *s {
+            NormalKey { .. }
+            | WeakKey { .. }
+            | OneShot { .. }
+            | Latched { .. }
+            | State::RepeatKey { .. } => None,
+            State::Custom { value, .. } | State::CustomPulse { value, .. } => {
+                let _ = all.push_back(CustomEvent::Release(value));
+                custom.update(CustomEvent::Release(value));
+                None
+            }
+            LayerModifier { .. } | State::StickyLayer { .. } | OneShotLayer { .. } => Some(*s),
+        });
+        custom
+    }
+
+    /// Pauses or resumes the layout, for example to freeze it while
+    /// a key combination to enter the bootloader is held.
+    ///
+    /// On pause, everything is flushed: the active keys, layers and
+    /// custom actions are released, and the waiting events, hold-taps
+    /// and macros are dropped. While paused, the events are ignored
+    /// (but still tracked by `recent_events` and
+    /// `unmatched_releases`), and `tick` does nothing. Returns the
+    /// `CustomEvent` corresponding to the released custom actions, if
+    /// any.
+    pub fn set_paused(&mut self, paused: bool) -> CustomEvent<T> {
+        self.paused = paused;
+        if !paused {
+            return CustomEvent::NoEvent;
+        }
+        self.flush()
+    }
+
+    /// Returns `true` if the layout is locked by an `Action::Lock`,
+    /// waiting for its unlock sequence.
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    /// Releases everything, and drops the waiting events, hold-taps
+    /// and macros. Returns the `CustomEvent` corresponding to the
+    /// released custom actions, if any.
+    fn flush(&mut self) -> CustomEvent<T> {
+        self.tap_locks.clear();
+        self.waiting = None;
+        self.deque.clear();
+        self.macro_player = None;
+        self.active_combos.clear();
+        let custom = self.clear_keys_keep_layers();
+        self.states.clear();
+        custom
+    }
+}
+
+trait MapRetain<T> {
+    fn map_retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&T) -> Option<T>;
+}
+
+impl<T, const S: usize> MapRetain<T> for Vec<T, { S }> {
+    fn map_retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> Option<T>,
+    {
+        let mut processed = 0;
+        while processed < self.len() {
+            let res = f(&self[processed]);
+            match res {
+                Some(t) => {
+                    self[processed] = t;
+                    processed += 1;
+                }
+                None => unsafe {
+                    self.swap_remove_unchecked(processed);
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use super::{Event::*, Layout, *};
+    use crate::action::Action::*;
+    use crate::action::HoldTapConfig;
+    use crate::action::{d, k, l, m};
+    use crate::key_code::KeyCode;
     use crate::key_code::KeyCode::*;
     use std::collections::BTreeSet;
 
@@ -514,6 +2577,7 @@ mod test {
                     tap: &k(Space),
                     config: HoldTapConfig::Default,
                     tap_hold_interval: 0,
+                    on_timeout: None,
                 },
                 HoldTap {
                     timeout: 200,
@@ -521,6 +2585,7 @@ mod test {
                     tap: &k(Enter),
                     config: HoldTapConfig::Default,
                     tap_hold_interval: 0,
+                    on_timeout: None,
                 },
             ]],
             [[Trans, m(&[LCtrl, Enter])]],
@@ -533,237 +2598,3326 @@ mod test {
         assert_keys(&[], layout.keycodes());
         layout.event(Press(0, 0));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
-        layout.event(Release(0, 0));
-        for _ in 0..197 {
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 0));
+        for _ in 0..197 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, Space], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn hold_tap_interleaved_timeout() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LAlt),
+                tap: &k(Space),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            HoldTap {
+                timeout: 20,
+                hold: &k(LCtrl),
+                tap: &k(Enter),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 1));
+        for _ in 0..15 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+        for _ in 0..10 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[Space], layout.keycodes());
+        }
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space, LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn hold_on_press() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LAlt),
+                tap: &k(Space),
+                config: HoldTapConfig::HoldOnOtherKeyPress,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(Enter),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // Press another key before timeout
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt, Enter], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Enter], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // Press another key after timeout
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 0));
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt, Enter], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Enter], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn home_row_mods() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LCtrl),
+                tap: &k(A),
+                config: HoldTapConfig::PermissiveHold,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            HoldTap {
+                timeout: 200,
+                hold: &k(LShift),
+                tap: &k(S),
+                config: HoldTapConfig::PermissiveHold,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // hold the first mod, tap the second one while the first is
+        // still pending
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, S], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // tap the second mod while the first one is pending, with the
+        // first one released before it: both are taps, in order
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A, S], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[S], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn layer_default_action() {
+        static LAYERS: Layers<NoCustom, 3, 1, 3> = [
+            [[k(A), l(1), l(2)]],
+            [[NoOp, Trans, Trans]],
+            [[NoOp, Trans, Trans]],
+        ];
+        static FALLBACK: Action<NoCustom> = k(Escape);
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_layer_default_action(1, &Trans);
+        layout.set_layer_default_action(2, &FALLBACK);
+
+        // transparent to the default layer
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // configured fallback action
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Escape], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // out of range layers are ignored
+        layout.set_layer_default_action(3, &FALLBACK);
+    }
+
+    #[test]
+    fn custom_pulse() {
+        static LAYERS: Layers<u8, 2, 1, 1> = [[[
+            Action::CustomPulse {
+                value: 42,
+                ticks: 3,
+            },
+            Custom(43),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // released after 3 ticks while still held
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::Press(&42), layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::Release(&42), layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // the release doesn't hide another custom event of the tick
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::Press(&42), layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::Press(&43), layout.tick());
+        assert_eq!(CustomEvent::Release(&42), layout.tick());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::Release(&43), layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+    }
+
+    #[test]
+    fn layer_relative() {
+        static LAYERS: Layers<NoCustom, 3, 1, 3> = [
+            [[k(A), d(2), LayerRelative(-1)]],
+            [[k(B), Trans, Trans]],
+            [[k(C), d(0), LayerRelative(-1)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+
+        // clamped to the layer 0
+        layout.event(Press(0, 2));
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.tick();
+        assert_eq!(0, layout.current_layer());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 2));
+        layout.tick();
+        layout.tick();
+
+        // from the layer 2 to the layer 1
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        layout.tick();
+        layout.tick();
+        assert_eq!(2, layout.current_layer());
+        layout.event(Press(0, 2));
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.tick();
+        assert_eq!(1, layout.current_layer());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.tick();
+        assert_eq!(2, layout.current_layer());
+    }
+
+    #[test]
+    fn take_custom_events() {
+        static LAYERS: Layers<u8, 2, 1, 1> = [[[
+            MultipleActions(&[Action::Custom(1), Action::Custom(2)]),
+            Action::Custom(3),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        assert_eq!(None, layout.take_custom_events().next());
+
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::Press(&1), layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::Press(&3), layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::Release(&1), layout.tick());
+        assert!(layout.take_custom_events().eq([
+            CustomEvent::Press(&1),
+            CustomEvent::Press(&2),
+            CustomEvent::Press(&3),
+            CustomEvent::Release(&1),
+            CustomEvent::Release(&2),
+        ]
+        .iter()
+        .copied()));
+        assert_eq!(None, layout.take_custom_events().next());
+
+        assert_eq!(CustomEvent::Release(&3), layout.clear_keys_keep_layers());
+        assert!(layout
+            .take_custom_events()
+            .eq(Some(CustomEvent::Release(&3))));
+    }
+
+    #[test]
+    fn custom_cycle() {
+        static LAYERS: Layers<u8, 2, 1, 1> = [[[
+            Action::CustomCycle(&[&1, &2, &3]),
+            Action::CustomCycle(&[&4, &5]),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        for &value in [&1, &2, &3, &1].iter() {
+            layout.event(Press(0, 0));
+            assert_eq!(CustomEvent::Press(value), layout.tick());
+            layout.event(Release(0, 0));
+            assert_eq!(CustomEvent::Release(value), layout.tick());
+        }
+
+        // each key has its own cycle
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::Press(&4), layout.tick());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::Press(&2), layout.tick());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::Release(&4), layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::Release(&2), layout.tick());
+    }
+
+    #[test]
+    fn latency() {
+        static LAYERS: Layers<NoCustom, 4, 1, 1> = [[[k(A), k(B), k(C), k(D)]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // a single press
+        for (j, &kc) in [A, B, C, D].iter().enumerate() {
+            layout.event(Press(0, j as u8));
+            for _ in 0..LATENCY_TICKS {
+                layout.tick();
+            }
+            assert_keys(&[kc], layout.keycodes());
+            layout.event(Release(0, j as u8));
+            for _ in 0..LATENCY_TICKS {
+                layout.tick();
+            }
+            assert_keys(&[], layout.keycodes());
+        }
+
+        // several presses at once
+        for j in 0..4 {
+            layout.event(Press(0, j));
+        }
+        for n in 1..=4 {
+            for _ in 0..LATENCY_TICKS {
+                layout.tick();
+            }
+            assert_eq!(n, layout.keycodes().count());
+        }
+    }
+
+    #[test]
+    fn custom_or_key() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+        static CUSTOM: AtomicBool = AtomicBool::new(true);
+        static LAYERS: Layers<u8, 1, 1, 1> = [[[CustomOrKey { value: 7, key: A }]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_custom_predicate(|&v| v == 7 && CUSTOM.load(Ordering::Relaxed));
+
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::Press(&7), layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::Release(&7), layout.tick());
+
+        CUSTOM.store(false, Ordering::Relaxed);
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn require_prior_idle() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            k(A),
+            HoldTap {
+                timeout: 200,
+                hold: &k(LCtrl),
+                tap: &k(F),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_require_prior_idle(100);
+
+        // the hold-tap is pressed 50 ticks after another key: tap
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Release(0, 0));
+        for _ in 0..49 {
+            layout.tick();
+        }
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[F], layout.keycodes());
+        for _ in 0..300 {
+            layout.tick();
+        }
+        assert_keys(&[F], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // after an idle gap: hold
+        for _ in 0..100 {
+            layout.tick();
+        }
+        layout.event(Press(0, 1));
+        for _ in 0..201 {
+            layout.tick();
+        }
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_keys(&[LCtrl, A], layout.keycodes());
+    }
+
+    #[test]
+    fn lock() {
+        static LAYERS: Layers<NoCustom, 4, 1, 1> = [[[
+            Lock {
+                unlock_sequence: &[(0, 2), (0, 3), (0, 2)],
+            },
+            k(A),
+            k(B),
+            k(C),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 1));
+        layout.tick();
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert!(layout.is_locked());
+        assert_keys(&[], layout.keycodes());
+
+        // wrong keys: no output, still locked
+        for &j in [1, 0, 2, 3, 3, 2, 0].iter() {
+            layout.event(Press(0, j));
+            layout.tick();
+            assert_keys(&[], layout.keycodes());
+            layout.event(Release(0, j));
+            layout.tick();
+        }
+        assert!(layout.is_locked());
+
+        // the unlock sequence, after a wrong start
+        for &j in [2, 2, 3, 2].iter() {
+            assert!(layout.is_locked());
+            layout.event(Press(0, j));
+            layout.tick();
+            assert_keys(&[], layout.keycodes());
+            layout.event(Release(0, j));
+            layout.tick();
+        }
+        assert!(!layout.is_locked());
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[A], layout.keycodes());
+    }
+
+    #[test]
+    fn lock_repeated_start() {
+        static LAYERS: Layers<NoCustom, 3, 1, 1> = [[[
+            Lock {
+                unlock_sequence: &[(0, 1), (0, 1), (0, 2)],
+            },
+            k(A),
+            k(B),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert!(layout.is_locked());
+
+        // a a a b: the third a restarts the sequence from a a
+        for &j in [1, 1, 1, 2].iter() {
+            assert!(layout.is_locked());
+            layout.event(Press(0, j));
+            layout.tick();
+            layout.event(Release(0, j));
+            layout.tick();
+        }
+        assert!(!layout.is_locked());
+
+        let seq = [(0, 1), (0, 2), (0, 1), (0, 3)];
+        assert_eq!(0, unlock_progress(&seq, 0, (0, 2)));
+        assert_eq!(1, unlock_progress(&seq, 0, (0, 1)));
+        assert_eq!(2, unlock_progress(&seq, 1, (0, 2)));
+        assert_eq!(1, unlock_progress(&seq, 1, (0, 1)));
+        assert_eq!(3, unlock_progress(&seq, 2, (0, 1)));
+        assert_eq!(2, unlock_progress(&seq, 3, (0, 2)));
+        assert_eq!(0, unlock_progress(&seq, 3, (0, 4)));
+    }
+
+    #[test]
+    fn double_tap_lock() {
+        static LAYERS: Layers<NoCustom, 2, 1, 2> = [
+            [[
+                DoubleTapLock {
+                    inner: &l(1),
+                    timeout: 100,
+                },
+                DoubleTapLock {
+                    inner: &k(LShift),
+                    timeout: 100,
+                },
+            ]],
+            [[Trans, k(A)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        let tap = |layout: &mut Layout<NoCustom, 2, 1, 2>, j| {
+            layout.event(Press(0, j));
+            layout.tick();
+            layout.event(Release(0, j));
+            layout.tick();
+        };
+
+        // a single press is momentary
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_eq!(1, layout.current_layer());
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_eq!(0, layout.current_layer());
+
+        // a double tap locks the layer, until the next double tap
+        tap(&mut layout, 0);
+        for _ in 0..50 {
+            layout.tick();
+        }
+        tap(&mut layout, 0);
+        for _ in 0..200 {
+            layout.tick();
+        }
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.tick();
+        tap(&mut layout, 0);
+        assert_eq!(1, layout.current_layer());
+        tap(&mut layout, 0);
+        assert_eq!(0, layout.current_layer());
+        assert!(layout.is_idle());
+
+        // taps too far apart don't lock
+        tap(&mut layout, 1);
+        for _ in 0..100 {
+            layout.tick();
+        }
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[LShift], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // the same with a modifier
+        tap(&mut layout, 1);
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[LShift], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.tick();
+        assert_keys(&[LShift], layout.keycodes());
+        tap(&mut layout, 1);
+        tap(&mut layout, 1);
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn double_tap_lock_full() {
+        static LAYERS: Layers<NoCustom, 9, 1, 1> = [[[
+            DoubleTapLock {
+                inner: &k(A),
+                timeout: 100,
+            },
+            DoubleTapLock {
+                inner: &k(B),
+                timeout: 100,
+            },
+            DoubleTapLock {
+                inner: &k(C),
+                timeout: 100,
+            },
+            DoubleTapLock {
+                inner: &k(D),
+                timeout: 100,
+            },
+            DoubleTapLock {
+                inner: &k(E),
+                timeout: 100,
+            },
+            DoubleTapLock {
+                inner: &k(F),
+                timeout: 100,
+            },
+            DoubleTapLock {
+                inner: &k(G),
+                timeout: 100,
+            },
+            DoubleTapLock {
+                inner: &k(H),
+                timeout: 100,
+            },
+            DoubleTapLock {
+                inner: &k(I),
+                timeout: 100,
+            },
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        let tap = |layout: &mut Layout<NoCustom, 9, 1, 1>, j| {
+            layout.event(Press(0, j));
+            layout.tick();
+            layout.event(Release(0, j));
+            layout.tick();
+        };
+
+        // all the slots are taken by the locked keys
+        for j in 0..8 {
+            tap(&mut layout, j);
+            tap(&mut layout, j);
+        }
+        assert_keys(&[A, B, C, D, E, F, G, H], layout.keycodes());
+
+        // the ninth key is momentary, and leaves the others locked
+        for _ in 0..2 {
+            layout.event(Press(0, 8));
+            layout.tick();
+            assert_keys(&[A, B, C, D, E, F, G, H, I], layout.keycodes());
+            layout.event(Release(0, 8));
+            layout.tick();
+            assert_keys(&[A, B, C, D, E, F, G, H], layout.keycodes());
+        }
+        tap(&mut layout, 7);
+        tap(&mut layout, 7);
+        assert_keys(&[A, B, C, D, E, F, G], layout.keycodes());
+    }
+
+    #[test]
+    fn stuck_threshold() {
+        use core::sync::atomic::{AtomicU8, Ordering};
+        static STUCK: AtomicU8 = AtomicU8::new(0);
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[k(A), k(B)]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_stuck_threshold(Some(100));
+        layout.set_stuck_hook(|coord, keycode| {
+            assert_eq!(((0, 1), B), (coord, keycode));
+            STUCK.fetch_add(1, Ordering::Relaxed);
+        });
+
+        layout.event(Press(0, 0));
+        for _ in 0..50 {
+            layout.tick();
+        }
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 1));
+        for _ in 0..99 {
+            layout.tick();
+        }
+        assert_eq!(0, STUCK.load(Ordering::Relaxed));
+        for _ in 0..200 {
+            layout.tick();
+        }
+        assert_eq!(1, STUCK.load(Ordering::Relaxed));
+        // not released
+        assert_keys(&[B], layout.keycodes());
+    }
+
+    #[test]
+    fn repeat_key() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            Action::RepeatKey {
+                key: PgDown,
+                initial_delay: 100,
+                interval: 20,
+            },
+            k(A),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_keys(&[PgDown], layout.keycodes());
+
+        // count the taps
+        let mut taps = 1;
+        let mut down = true;
+        for i in 1..=200 {
+            layout.tick();
+            let now = layout.keycodes().any(|kc| kc == PgDown);
+            if now && !down {
+                taps += 1;
+            }
+            down = now;
+            if i < 100 {
+                assert!(down);
+            }
+        }
+        // the first press, and then the repetitions after 101, 121,
+        // 141, 161 and 181 ticks
+        assert_eq!(6, taps);
+
+        // stopped by the release, not affecting the other keys
+        layout.event(Press(0, 1));
+        layout.tick();
+        layout.event(Release(0, 0));
+        layout.tick();
+        for _ in 0..200 {
+            layout.tick();
+            assert_keys(&[A], layout.keycodes());
+        }
+    }
+
+    #[test]
+    fn write_layer() {
+        use std::string::String;
+        static LAYERS: Layers<u8, 3, 2, 2> = [
+            [
+                [k(A), m(&[LCtrl, C]), l(1)],
+                [
+                    HoldTap {
+                        timeout: 200,
+                        hold: &k(LShift),
+                        tap: &k(Space),
+                        config: HoldTapConfig::Default,
+                        tap_hold_interval: 0,
+                        on_timeout: None,
+                    },
+                    d(1),
+                    NoOp,
+                ],
+            ],
+            [[Trans, k(Kb1), Trans], [Custom(3), Trans, Repeat]],
+        ];
+        let layout = Layout::new(&LAYERS);
+        let mut out = String::new();
+        layout.write_layer(0, &mut out).unwrap();
+        assert_eq!("A LCtrl+C L1\nSpace/LShift D1 -\n", out);
+
+        out.clear();
+        layout.write_layer(1, &mut out).unwrap();
+        assert_eq!("A Kb1 L1\nC D1 ?\n", out);
+
+        out.clear();
+        layout.write_layer(2, &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn sticky_layer() {
+        static LAYERS: Layers<NoCustom, 3, 1, 2> = [
+            [[
+                Action::StickyLayer {
+                    layer: 1,
+                    timeout: 50,
+                },
+                k(A),
+                k(B),
+            ]],
+            [[Trans, k(Kb1), k(Kb2)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+
+        // held: momentary
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[Kb1], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        layout.tick();
+        layout.tick();
+        assert_eq!(0, layout.current_layer());
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.tick();
+
+        // held too long without another key: momentary
+        layout.event(Press(0, 0));
+        for _ in 0..60 {
+            layout.tick();
+        }
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_eq!(0, layout.current_layer());
+        assert!(layout.is_idle());
+
+        // tapped: sticky for the next key only
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Release(0, 0));
+        for _ in 0..100 {
+            layout.tick();
+            assert_eq!(1, layout.current_layer());
+        }
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[Kb2], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[Kb2, A], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.event(Release(0, 1));
+        layout.tick();
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+        assert!(layout.is_idle());
+    }
+
+    #[test]
+    fn active_coords() {
+        static LAYERS: Layers<NoCustom, 3, 2, 2> = [
+            [[k(A), m(&[LCtrl, C]), l(1)], [k(D), k(E), k(F)]],
+            [[Trans, Trans, Trans], [k(Kb1), Trans, Trans]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        assert_eq!(0, layout.active_coords().count());
+        layout.event(Press(0, 1));
+        layout.event(Press(1, 2));
+        layout.tick();
+        layout.tick();
+        layout.press_keycode(Z);
+        let coords: BTreeSet<_> = layout.active_coords().collect();
+        assert_eq!(
+            [(0, 1), (1, 2)].iter().copied().collect::<BTreeSet<_>>(),
+            coords
+        );
+        assert_eq!(2, layout.active_coords().count());
+
+        layout.event(Press(0, 2));
+        layout.event(Release(0, 1));
+        layout.tick();
+        layout.tick();
+        let coords: BTreeSet<_> = layout.active_coords().collect();
+        assert_eq!(
+            [(0, 2), (1, 2)].iter().copied().collect::<BTreeSet<_>>(),
+            coords
+        );
+    }
+
+    #[test]
+    fn nested_hold_tap() {
+        static INNER: Action<NoCustom> = HoldTap {
+            timeout: 100,
+            hold: &k(LCtrl),
+            tap: &k(LShift),
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+            on_timeout: None,
+        };
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: 100,
+                hold: &INNER,
+                tap: &k(Space),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(A),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // tap: the tap of the outer hold-tap
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[Space], layout.keycodes());
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // held past the first timeout: the tap of the inner hold-tap
+        layout.event(Press(0, 0));
+        for _ in 0..150 {
+            layout.tick();
+            assert_keys(&[], layout.keycodes());
+        }
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[LShift], layout.keycodes());
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // held past both timeouts: the hold of the inner hold-tap
+        layout.event(Press(0, 0));
+        for _ in 0..250 {
+            layout.tick();
+        }
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[LCtrl, A], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        layout.tick();
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+        assert!(layout.is_idle());
+
+        // as the tap action: its tap action
+        static TAP_LAYERS: Layers<NoCustom, 1, 1, 1> = [[[HoldTap {
+            timeout: 100,
+            hold: &k(RAlt),
+            tap: &INNER,
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+            on_timeout: None,
+        }]]];
+        let mut layout = Layout::new(&TAP_LAYERS);
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Release(0, 0));
+        layout.tick();
+        layout.tick();
+        assert_keys(&[LShift], layout.keycodes());
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+        assert!(layout.is_idle());
+    }
+
+    #[test]
+    fn cosmetic_layers() {
+        static LAYERS: Layers<NoCustom, 3, 1, 4> = [
+            [[l(1), l(2), k(A)]],
+            [[Trans, Trans, k(B)]],
+            [[Trans, Trans, Trans]],
+            [[Trans, Trans, k(C)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_cosmetic_layers(&[false, false, true, false]);
+
+        // the cosmetic layer is reported, but the keys resolve to
+        // the base layer
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_eq!(2, layout.current_layer());
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.tick();
+
+        // with another layer: the cosmetic layer doesn't shift it
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_eq!(3, layout.current_layer());
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        for _ in 0..3 {
+            layout.tick();
+        }
+        assert_eq!(0, layout.current_layer());
+    }
+
+    #[test]
+    fn count_presses_in_window() {
+        let mut deque = Deque::new();
+        let mut push = |event, since| {
+            deque.push_back(Stacked { event, since });
+        };
+        push(Press(0, 0), 300);
+        push(Release(0, 0), 250);
+        push(Press(0, 1), 200);
+        push(Press(0, 0), 120);
+        push(Release(0, 0), 100);
+        push(Press(0, 0), 20);
+        push(Press(0, 0), 0);
+        assert_eq!(0, count_presses(&deque, (1, 0), u16::MAX));
+        assert_eq!(1, count_presses(&deque, (0, 1), u16::MAX));
+        assert_eq!(4, count_presses(&deque, (0, 0), u16::MAX));
+        assert_eq!(3, count_presses(&deque, (0, 0), 120));
+        assert_eq!(2, count_presses(&deque, (0, 0), 119));
+        assert_eq!(1, count_presses(&deque, (0, 0), 0));
+        assert_eq!(0, count_presses(&deque, (0, 1), 199));
+    }
+
+    #[test]
+    fn layer_overflow_policy() {
+        static LAYERS: Layers<NoCustom, 3, 1, 3> = [
+            [[l(1), l(2), k(A)]],
+            [[Trans, Trans, k(B)]],
+            [[Trans, Trans, k(C)]],
+        ];
+        let expected = [
+            (LayerOverflowPolicy::Ignore, 3, &[][..]),
+            (LayerOverflowPolicy::Wrap, 0, &[A]),
+            (LayerOverflowPolicy::Clamp, 2, &[C]),
+        ];
+        for &(policy, layer, kcs) in expected.iter() {
+            let mut layout = Layout::new(&LAYERS);
+            layout.set_layer_overflow_policy(policy);
+            layout.event(Press(0, 0));
+            layout.tick();
+            layout.event(Press(0, 1));
+            layout.tick();
+            assert_eq!(layer, layout.current_layer());
+            layout.event(Press(0, 2));
+            layout.tick();
+            assert_keys(kcs, layout.keycodes());
+        }
+    }
+
+    #[test]
+    fn dimensions() {
+        static LAYERS: Layers<NoCustom, 12, 4, 2> = [[[NoOp; 12]; 4]; 2];
+        let layout = Layout::new(&LAYERS);
+        assert_eq!(12, layout.cols());
+        assert_eq!(4, layout.rows());
+        assert_eq!(2, layout.layers());
+    }
+
+    #[test]
+    fn hold_tap_on_timeout() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LCtrl),
+                tap: &k(Space),
+                config: HoldTapConfig::HoldOnOtherKeyPress,
+                tap_hold_interval: 0,
+                on_timeout: Some(&k(LAlt)),
+            },
+            k(A),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // held alone: the timeout action
+        layout.event(Press(0, 0));
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt, A], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // held with an interleaved press: the hold action
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, A], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // tapped: the tap action
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        static LAYERS: Layers<NoCustom, 3, 1, 2> = [
+            [[
+                HoldTap {
+                    timeout: 200,
+                    hold: &k(LCtrl),
+                    tap: &k(Space),
+                    config: HoldTapConfig::Default,
+                    tap_hold_interval: 0,
+                    on_timeout: None,
+                },
+                l(1),
+                k(A),
+            ]],
+            [[Trans, Trans, k(B)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 1));
+        layout.tick();
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Press(0, 2));
+        layout.tick();
+        let snap = layout.snapshot();
+
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 1));
+        for _ in 0..3 {
+            layout.tick();
+        }
+        layout.set_default_layer(1);
+        assert_keys(&[B], layout.keycodes());
+
+        // the hold-tap is waiting again, with (0, 2) stacked
+        layout.restore(&snap);
+        assert_eq!(1, layout.current_layer());
+        assert_keys(&[], layout.keycodes());
+        for _ in 0..200 {
+            layout.tick();
+        }
+        assert_keys(&[LCtrl, B], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        for _ in 0..3 {
+            layout.tick();
+        }
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+    }
+
+    #[test]
+    fn modifier_timeout() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[k(LShift), k(A)]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_modifier_timeout(Some(100));
+
+        // a modifier held alone is released after the timeout
+        layout.event(Press(0, 0));
+        for _ in 0..99 {
+            layout.tick();
+        }
+        assert_keys(&[LShift], layout.keycodes());
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // a modifier used with a key stays pressed
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Press(0, 1));
+        for _ in 0..200 {
+            layout.tick();
+        }
+        assert_keys(&[LShift, A], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        layout.tick();
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn last_keycode() {
+        static LAYERS: Layers<NoCustom, 3, 1, 1> = [[[k(A), k(B), k(LShift)]]];
+        let mut layout = Layout::new(&LAYERS);
+        assert_eq!(None, layout.last_keycode());
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_eq!(Some(A), layout.last_keycode());
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_eq!(Some(B), layout.last_keycode());
+        layout.event(Release(0, 1));
+        layout.tick();
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_eq!(Some(B), layout.last_keycode());
+    }
+
+    #[test]
+    fn weak_keycode() {
+        static LAYERS: Layers<NoCustom, 7, 1, 1> =
+            [[[k(A), WeakKeyCode(B), k(C), k(D), k(E), k(F), k(G)]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[B], layout.keycodes());
+        assert_eq!(&[0, 0, 5, 0, 0, 0, 0, 0], layout.hid_report().as_bytes());
+
+        // at 7 keys, the weak key is dropped
+        for j in [0, 2, 3, 4, 5, 6].iter() {
+            layout.event(Press(0, *j));
+            layout.tick();
+        }
+        assert_keys(&[A, B, C, D, E, F, G], layout.keycodes());
+        assert_eq!(&[0, 0, 4, 6, 7, 8, 9, 10], layout.hid_report().as_bytes());
+
+        // and sent again once a slot is free
+        layout.event(Release(0, 6));
+        layout.tick();
+        assert_eq!(&[0, 0, 4, 6, 7, 8, 9, 5], layout.hid_report().as_bytes());
+        layout.event(Release(0, 1));
+        layout.tick();
+        assert_keys(&[A, C, D, E, F], layout.keycodes());
+        assert!(!layout.hid_report().as_bytes().contains(&5));
+    }
+
+    #[test]
+    fn rollover_exceeded() {
+        static LAYERS: Layers<NoCustom, 8, 1, 1> =
+            [[[k(LShift), k(A), k(B), k(C), k(D), k(E), k(F), k(G)]]];
+        let mut layout = Layout::new(&LAYERS);
+        for j in 0..7 {
+            layout.event(Press(0, j));
+            layout.tick();
+        }
+        assert_keys(&[LShift, A, B, C, D, E, F], layout.keycodes());
+        assert!(!layout.rollover_exceeded());
+        layout.event(Press(0, 7));
+        layout.tick();
+        assert!(layout.rollover_exceeded());
+        layout.event(Release(0, 1));
+        layout.tick();
+        assert!(!layout.rollover_exceeded());
+    }
+
+    #[test]
+    fn mod_tap() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[crate::action::mt(&k(LCtrl), &k(A)), k(B)]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // tap
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[A], layout.keycodes());
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // hold after the timeout
+        layout.event(Press(0, 0));
+        for _ in 0..200 {
+            layout.tick();
+            assert_keys(&[], layout.keycodes());
+        }
+        layout.tick();
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[LCtrl, B], layout.keycodes());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn pending_events() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LAlt),
+                tap: &k(Space),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(Enter),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert!(layout.pending_events().next().is_none());
+        layout.event(Press(0, 1));
+        layout.tick();
+        layout.tick();
+        layout.event(Release(0, 1));
+        layout.tick();
+        let expected = [(Press(0, 1), 3), (Release(0, 1), 1)];
+        assert!(layout.pending_events().eq(expected.iter().copied()));
+    }
+
+    #[test]
+    fn hold_tap_default_layer() {
+        static LAYERS: Layers<NoCustom, 2, 1, 2> = [
+            [[
+                HoldTap {
+                    timeout: 200,
+                    hold: &d(1),
+                    tap: &k(Escape),
+                    config: HoldTapConfig::Default,
+                    tap_hold_interval: 0,
+                    on_timeout: None,
+                },
+                k(A),
+            ]],
+            [[d(0), k(B)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+
+        // the hold action switches the default layer, that persists
+        // after the release
+        layout.event(Press(0, 0));
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_eq!(0, layout.current_layer());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // switch back
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(0, layout.current_layer());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // the tap action is performed on a tap
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Escape], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+    }
+
+    #[test]
+    fn unmatched_releases() {
+        static LAYERS: Layers<NoCustom, 2, 1, 2> = [[[l(1), k(A)]], [[Trans, k(B)]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 1));
+        layout.tick();
+        layout.tick();
+        assert_keys(&[B], layout.keycodes());
+        assert_eq!(0, layout.unmatched_releases());
+
+        layout.event(Release(1, 1));
+        layout.tick();
+        assert_eq!(1, layout.unmatched_releases());
+        assert_keys(&[B], layout.keycodes());
+        assert_eq!(1, layout.current_layer());
+
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 0));
+        layout.tick();
+        layout.tick();
+        layout.tick();
+        assert_eq!(2, layout.unmatched_releases());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+    }
+
+    #[test]
+    fn permissive_hold() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LAlt),
+                tap: &k(Space),
+                config: HoldTapConfig::PermissiveHold,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(Enter),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // Press and release another key before timeout
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt, Enter], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn multiple_actions() {
+        static LAYERS: Layers<NoCustom, 2, 1, 2> = [
+            [[MultipleActions(&[l(1), k(LShift)]), k(F)]],
+            [[Trans, k(E)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, E], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn tick_n() {
+        static LAYERS: Layers<u8, 3, 1, 1> = [[[
+            HoldTap {
+                timeout: 10,
+                hold: &k(LCtrl),
+                tap: &k(A),
+                config: HoldTapConfig::HoldOnOtherKeyPress,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(B),
+            Action::Custom(42),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // the hold-tap is resolved by the press of B before its
+        // timeout, and B is pressed at the next tick
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        assert_eq!((3, CustomEvent::NoEvent), layout.tick_n(3));
+        assert_keys(&[LCtrl, B], layout.keycodes());
+        assert_eq!((20, CustomEvent::NoEvent), layout.tick_n(20));
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.tick_n(20);
+        assert_keys(&[], layout.keycodes());
+
+        // a tap within the same batch
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.tick_n(20);
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.tick_n(2);
+        assert_keys(&[A], layout.keycodes());
+        layout.tick_n(1);
+        assert_keys(&[], layout.keycodes());
+
+        // stops at the custom event
+        layout.event(Press(0, 2));
+        layout.event(Release(0, 2));
+        assert_eq!((1, CustomEvent::Press(&42)), layout.tick_n(20));
+        assert_eq!((1, CustomEvent::Release(&42)), layout.tick_n(20));
+        assert_eq!((20, CustomEvent::NoEvent), layout.tick_n(20));
+    }
+
+    #[test]
+    fn auto_shift() {
+        static LAYERS: Layers<NoCustom, 3, 1, 2> =
+            [[[k(A), l(1), k(Space)]], [[k(Kb1), Trans, Trans]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_auto_shift(10, &[true, false]);
+
+        // tap
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[A], layout.keycodes());
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // hold
+        layout.event(Press(0, 0));
+        for _ in 0..10 {
+            layout.tick();
+            assert_keys(&[], layout.keycodes());
+        }
+        layout.tick();
+        assert_keys(&[LShift, A], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // Space is not auto-shifted
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[Space], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.tick();
+
+        // the auto-shift is disabled on the layer 1
+        layout.event(Press(0, 1));
+        layout.tick();
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_keys(&[Kb1], layout.keycodes());
+        for _ in 0..20 {
+            layout.tick();
+        }
+        assert_keys(&[Kb1], layout.keycodes());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn invariants() {
+        static LAYERS: Layers<u8, 4, 2, 3> = [
+            [
+                [
+                    k(A),
+                    m(&[LShift, A]),
+                    HoldTap {
+                        timeout: 20,
+                        hold: &l(1),
+                        tap: &k(Space),
+                        config: HoldTapConfig::PermissiveHold,
+                        tap_hold_interval: 0,
+                        on_timeout: None,
+                    },
+                    OneShot(LShift),
+                ],
+                [
+                    Action::Custom(1),
+                    LatchKeyCode(LCtrl),
+                    MultipleActions(&[l(2), k(LAlt)]),
+                    Action::Repeat,
+                ],
+            ],
+            [
+                [k(B), Trans, Trans, OneShot(LShift)],
+                [d(2), Trans, Trans, Trans],
+            ],
+            [
+                [k(C), d(0), l(1), LayerRelative(-1)],
+                [Trans, Trans, Trans, Trans],
+            ],
+        ];
+        static COMBOS: [Combo<u8>; 1] = [Combo {
+            keys: &[(0, 0), (0, 1)],
+            action: &k(Escape),
+        }];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_combos(&COMBOS, 10);
+
+        // a deterministic pseudo random stream of events, including
+        // out of range coordinates
+        let mut seed = 0x1234_5678u32;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        for _ in 0..20_000 {
+            let r = next();
+            let (i, j) = if r & 0xff == 0 {
+                ((r >> 8) as u8, (r >> 16) as u8)
+            } else {
+                ((r >> 8) as u8 % 2, (r >> 16) as u8 % 4)
+            };
+            match r >> 24 & 3 {
+                0 => drop(layout.event(Press(i, j))),
+                1 => drop(layout.event(Release(i, j))),
+                _ => drop(layout.tick()),
+            }
+            layout.check_invariants();
+        }
+    }
+
+    #[test]
+    fn event_ordering() {
+        assert_eq!(Press(1, 2), Event::press((1, 2)));
+        assert_eq!(Release(1, 2), Event::release((1, 2)));
+        assert_eq!(Event::release(Press(3, 4).coord()), Release(3, 4));
+
+        let mut events = [
+            Release(0, 1),
+            Press(1, 0),
+            Release(0, 0),
+            Press(0, 3),
+            Press(0, 3),
+        ];
+        events.sort_unstable();
+        assert_eq!(
+            [
+                Press(0, 3),
+                Press(0, 3),
+                Press(1, 0),
+                Release(0, 0),
+                Release(0, 1)
+            ],
+            events
+        );
+        assert!(Press(9, 9) < Release(0, 0));
+    }
+
+    #[test]
+    fn multiple_actions_with_hold_tap() {
+        static LAYERS: Layers<NoCustom, 2, 1, 2> = [
+            [[
+                MultipleActions(&[
+                    HoldTap {
+                        timeout: 200,
+                        hold: &k(LAlt),
+                        tap: &k(Space),
+                        config: HoldTapConfig::Default,
+                        tap_hold_interval: 0,
+                        on_timeout: None,
+                    },
+                    MultipleActions(&[
+                        k(LShift),
+                        HoldTap {
+                            timeout: 200,
+                            hold: &k(LGui),
+                            tap: &k(Tab),
+                            config: HoldTapConfig::Default,
+                            tap_hold_interval: 0,
+                            on_timeout: None,
+                        },
+                    ]),
+                    l(1),
+                ]),
+                k(F),
+            ]],
+            [[Trans, k(E)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+
+        // tap: the other actions are performed at once
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_keys(&[LShift], layout.keycodes());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[LShift, Space], layout.keycodes());
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+
+        // hold, the second hold-tap being ignored
+        layout.event(Press(0, 0));
+        for _ in 0..201 {
+            layout.tick();
+        }
+        assert_keys(&[LShift, LAlt], layout.keycodes());
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[LShift, LAlt, E], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        layout.tick();
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn multiple_custom_actions() {
+        static LAYERS: Layers<u8, 1, 1, 1> = [[[MultipleActions(&[
+            Action::Custom(1),
+            Action::Custom(2),
+            Action::Custom(3),
+        ])]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // Custom event
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::Press(&1), layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // nothing more
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // release custom
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::Release(&1), layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn custom() {
+        static LAYERS: Layers<u8, 1, 1, 1> = [[[Action::Custom(42)]]];
+        let mut layout = Layout::new(&LAYERS);
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // Custom event
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::Press(&42), layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // nothing more
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // release custom
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::Release(&42), layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn clear_keys_keep_layers() {
+        static LAYERS: Layers<u8, 3, 1, 2> = [
+            [[l(1), k(A), Action::Custom(42)]],
+            [[Trans, k(B), Action::Custom(43)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::Press(&43), layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        assert_eq!(1, layout.current_layer());
+
+        // keys and customs are released, but the layer stays active
+        assert_eq!(CustomEvent::Release(&43), layout.clear_keys_keep_layers());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(1, layout.current_layer());
+
+        // physical releases of the cleared keys do nothing
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(0, layout.current_layer());
+    }
+
+    #[test]
+    fn remap() {
+        static LAYERS: Layers<NoCustom, 2, 2, 2> = [
+            [[k(A), k(B)], [k(C), k(D)]],
+            [[k(Kb1), k(Kb2)], [k(Kb3), Trans]],
+        ];
+        // serpentine wiring: the second row is reversed
+        static MAP: [[(u8, u8); 2]; 2] = [[(0, 0), (0, 1)], [(1, 1), (1, 0)]];
+        let mut remapped: Layers<NoCustom, 2, 2, 2> = [[[NoOp; 2]; 2]; 2];
+        remap_into(&LAYERS, &MAP, &mut remapped);
+        assert_eq!(k(A), remapped[0][0][0]);
+        assert_eq!(k(D), remapped[0][1][0]);
+        assert_eq!(k(C), remapped[0][1][1]);
+        assert_eq!(Trans, remapped[1][1][0]);
+        assert_eq!(k(Kb3), remapped[1][1][1]);
+
+        // out of bounds coordinates give NoOp
+        remap_into(
+            &LAYERS,
+            &[[(0, 0), (0, 2)], [(2, 0), (1, 0)]],
+            &mut remapped,
+        );
+        assert_eq!(NoOp, remapped[0][0][1]);
+        assert_eq!(NoOp, remapped[0][1][0]);
+        assert_eq!(k(C), remapped[0][1][1]);
+    }
+
+    #[cfg(feature = "holdtap-stats")]
+    #[test]
+    fn holdtap_stats() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LAlt),
+                tap: &k(Space),
+                config: HoldTapConfig::HoldOnOtherKeyPress,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(Enter),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        assert_eq!(HoldTapStats::default(), layout.holdtap_stats());
+
+        // tap
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Release(0, 0));
+        for _ in 0..3 {
+            layout.tick();
+        }
+        // hold on other key press
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Press(0, 1));
+        layout.tick();
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        for _ in 0..5 {
+            layout.tick();
+        }
+        // hold by timeout
+        layout.event(Press(0, 0));
+        for _ in 0..202 {
+            layout.tick();
+        }
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        let stats = layout.holdtap_stats();
+        assert_eq!(1, stats.taps);
+        assert_eq!(2, stats.holds);
+        assert_eq!(1, stats.timeouts);
+    }
+
+    #[test]
+    fn one_shot_stacking() {
+        static LAYERS: Layers<NoCustom, 4, 1, 1> =
+            [[[OneShot(LShift), OneShot(LCtrl), k(A), k(RAlt)]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // arm the two one shots
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, LCtrl], layout.keycodes());
+
+        // a modifier doesn't consume the one shots
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, LCtrl], layout.keycodes());
+
+        // both apply to the next key
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, LCtrl, A], layout.keycodes());
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // and then all are cleared
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn default_layer_clean() {
+        static LAYERS: Layers<NoCustom, 3, 1, 2> = [
+            [[k(A), k(LShift), m(&[LCtrl, C])]],
+            [[k(B), k(LShift), k(C)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        for j in 0..3 {
+            layout.event(Press(0, j));
+            layout.tick();
+        }
+        assert_keys(&[A, LShift, LCtrl, C], layout.keycodes());
+
+        // A and LCtrl are not sent on the new layer
+        layout.set_default_layer_clean(1);
+        assert_eq!(1, layout.current_layer());
+        assert_keys(&[LShift, C], layout.keycodes());
+
+        // the releases have no effect, and the keys work normally
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[LShift, C], layout.keycodes());
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_keys(&[LShift, C, B], layout.keycodes());
+        for j in 0..3 {
+            layout.event(Release(0, j));
+            layout.tick();
+        }
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn default_layer_clean_weak_and_repeat() {
+        static LAYERS: Layers<NoCustom, 3, 1, 2> = [
+            [[
+                WeakKeyCode(A),
+                WeakKeyCode(LShift),
+                Action::RepeatKey {
+                    key: C,
+                    initial_delay: 100,
+                    interval: 10,
+                },
+            ]],
+            [[k(B), WeakKeyCode(LShift), k(D)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        for j in 0..3 {
+            layout.event(Press(0, j));
+            layout.tick();
+        }
+        assert_keys(&[A, LShift, C], layout.keycodes());
+
+        // the weak A and the repeated C are not sent on the new layer
+        layout.set_default_layer_clean(1);
+        assert_keys(&[LShift], layout.keycodes());
+        for _ in 0..200 {
+            layout.tick();
+        }
+        assert_keys(&[LShift], layout.keycodes());
+
+        for j in 0..3 {
+            layout.event(Release(0, j));
+            layout.tick();
+        }
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn one_shot_cancel() {
+        static LAYERS: Layers<NoCustom, 3, 1, 1> = [[[OneShot(LShift), OneShot(LCtrl), k(A)]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // arm and cancel LShift, arm LCtrl
+        for &j in [0, 1, 0].iter() {
+            layout.event(Press(0, j));
+            layout.tick();
+            layout.event(Release(0, j));
+            layout.tick();
+        }
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[LCtrl, A], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.tick();
+
+        // the next key is unmodified
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Release(0, 0));
+        layout.tick();
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[A], layout.keycodes());
+    }
+
+    #[test]
+    fn one_shot_cancel_same_key_only() {
+        static LAYERS: Layers<NoCustom, 3, 1, 1> = [[[OneShot(LShift), OneShot(LShift), k(A)]]];
+        let mut layout = Layout::new(&LAYERS);
+        let tap = |layout: &mut Layout<NoCustom, 3, 1, 1>, j| {
+            layout.event(Press(0, j));
+            layout.tick();
+            layout.event(Release(0, j));
+            layout.tick();
+        };
+
+        // the other key with the same modifier doesn't cancel it
+        tap(&mut layout, 0);
+        tap(&mut layout, 1);
+        assert_keys(&[LShift], layout.keycodes());
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[LShift, A], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // re-tapping the first one cancels only its one shot
+        tap(&mut layout, 0);
+        tap(&mut layout, 1);
+        tap(&mut layout, 0);
+        assert_keys(&[LShift], layout.keycodes());
+        tap(&mut layout, 1);
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[A], layout.keycodes());
+    }
+
+    #[test]
+    fn injected_keycodes() {
+        static LAYERS: Layers<NoCustom, 1, 1, 1> = [[[k(A)]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.press_keycode(LCtrl);
+        layout.press_keycode(C);
+        assert_keys(&[A, LCtrl, C], layout.keycodes());
+
+        // matrix events don't interact with injected key codes
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, C], layout.keycodes());
+
+        layout.release_keycode(C);
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.release_keycode(LCtrl);
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn hold_tap_since_overflow() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: u16::MAX,
+                hold: &k(LAlt),
+                tap: &k(Space),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            HoldTap {
+                timeout: 200,
+                hold: &k(LCtrl),
+                tap: &k(Enter),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // press and release stacked during the same tick
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Enter], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // stacked events saturating their `since`
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        for _ in 0..70_000 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            if layout.keycodes().any(|kc| kc == Enter) {
+                break;
+            }
+        }
+        assert_keys(&[LAlt, Enter], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[cfg(feature = "defmt")]
+    #[test]
+    fn defmt_format() {
+        fn assert_format<F: defmt::Format>() {}
+        assert_format::<Event>();
+        assert_format::<CustomEvent<u8>>();
+        assert_format::<KeyCode>();
+    }
+
+    #[test]
+    fn same_layer_held_by_several_keys() {
+        static LAYERS: Layers<NoCustom, 4, 1, 4> = [
+            [[l(1), l(1), l(2), k(A)]],
+            [[Trans, Trans, Trans, k(B)]],
+            [[Trans, Trans, Trans, k(C)]],
+            [[Trans, Trans, Trans, k(D)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+
+        // different layers are still summed
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(3, layout.current_layer());
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+
+        // the layer is active until all the keys are released
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 3));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(0, layout.current_layer());
+    }
+
+    #[test]
+    fn keycode_remap() {
+        static LAYERS: Layers<NoCustom, 3, 1, 1> = [[[k(Y), k(Z), k(A)]]];
+        static QWERTZ: [KeyCode; 256] = {
+            let mut remap = [No; 256];
+            remap[Y as usize] = Z;
+            remap[Z as usize] = Y;
+            remap
+        };
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_keycode_remap(Some(&QWERTZ));
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Z], layout.keycodes());
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Z, A], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Y, A], layout.keycodes());
+
+        // without table, identity
+        layout.set_keycode_remap(None);
+        assert_keys(&[Z, A], layout.keycodes());
+    }
+
+    #[test]
+    fn process_scan() {
+        static LAYERS: Layers<u8, 2, 1, 1> = [[[k(A), Action::Custom(42)]]];
+        let mut layout = Layout::new(&LAYERS);
+        assert_eq!(
+            CustomEvent::NoEvent,
+            layout.process_scan(None::<[Event; 0]>)
+        );
+        assert_eq!(
+            CustomEvent::NoEvent,
+            layout.process_scan(Some([Press(0, 0), Press(0, 1)].iter().copied()))
+        );
+        assert_keys(&[A], layout.keycodes());
+        assert_eq!(
+            CustomEvent::Press(&42),
+            layout.process_scan(None::<[Event; 0]>)
+        );
+        assert_eq!(
+            CustomEvent::NoEvent,
+            layout.process_scan(Some([Release(0, 0)].iter().copied()))
+        );
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn tap_dance_mashing() {
+        static LAYERS: Layers<NoCustom, 1, 1, 1> = [[[TapDanceHold {
+            timeout: 100,
+            tap: &k(Space),
+            hold: &k(LCtrl),
+            double: &k(Enter),
+        }]]];
+        let mut layout = Layout::new(&LAYERS);
+        // the key codes pressed over time
+        let mut performed = std::vec::Vec::new();
+        let mut active = std::vec::Vec::new();
+        let mut record = |layout: &Layout<NoCustom, 1, 1, 1>| {
+            let now: std::vec::Vec<_> = layout.keycodes().collect();
+            performed.extend(now.iter().filter(|kc| !active.contains(*kc)));
+            active = now;
+        };
+        for _ in 0..5 {
+            layout.event(Press(0, 0));
+            for _ in 0..10 {
+                layout.tick();
+                record(&layout);
+            }
+            layout.event(Release(0, 0));
+            for _ in 0..10 {
+                layout.tick();
+                record(&layout);
+            }
+        }
+        for _ in 0..200 {
+            layout.tick();
+            record(&layout);
+        }
+        assert_eq!(&[Enter, Enter, Space][..], &performed[..]);
+        assert_keys(&[], layout.keycodes());
+        assert!(layout.is_idle());
+    }
+
+    #[test]
+    fn tap_dance_hold() {
+        static LAYERS: Layers<NoCustom, 2, 1, 2> = [
+            [[
+                TapDanceHold {
+                    timeout: 100,
+                    tap: &k(Space),
+                    hold: &l(1),
+                    double: &d(1),
+                },
+                k(A),
+            ]],
+            [[
+                TapDanceHold {
+                    timeout: 100,
+                    tap: &k(Space),
+                    hold: &l(1),
+                    double: &d(0),
+                },
+                k(B),
+            ]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+
+        // tap
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        for _ in 0..99 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+
+        // hold
+        layout.event(Press(0, 0));
+        for _ in 0..100 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_eq!(0, layout.current_layer());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+
+        // double tap
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        for _ in 0..20 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn history() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[k(A), k(B)]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        assert_eq!(0, layout.recent_events().count());
+
+        let mut layout: Layout<_, 2, 1, 1, 4> = Layout::with_history(&LAYERS);
+        assert_eq!(0, layout.recent_events().count());
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        let expected = [Press(0, 0), Press(0, 1)];
+        assert!(layout.recent_events().eq(expected.iter().copied()));
+
+        // only the 4 most recent events are kept
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 1));
+        layout.event(Press(0, 1));
+        let expected = [Press(0, 1), Release(0, 0), Release(0, 1), Press(0, 1)];
+        assert!(layout.recent_events().eq(expected.iter().copied()));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[B], layout.keycodes());
+    }
+
+    #[test]
+    fn holdtap_disabled() {
+        static LAYERS: Layers<NoCustom, 1, 1, 1> = [[[HoldTap {
+            timeout: 200,
+            hold: &k(LAlt),
+            tap: &k(Space),
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+            on_timeout: None,
+        }]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // disabled: immediate tap, even when held
+        layout.set_holdtap_enabled(false);
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+        for _ in 0..300 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[Space], layout.keycodes());
+        }
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // enabled: waiting for the resolution
+        layout.set_holdtap_enabled(true);
+        layout.event(Press(0, 0));
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn repeat() {
+        static LAYERS: Layers<NoCustom, 4, 1, 1> =
+            [[[k(LCtrl), k(Z), Action::Repeat, m(&[LShift, A])]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // no previous key code
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.tick();
+
+        // Ctrl+Z
+        layout.event(Press(0, 0));
+        layout.tick();
+        layout.event(Press(0, 1));
+        layout.tick();
+        layout.event(Release(0, 1));
+        layout.tick();
+        layout.event(Release(0, 0));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[LCtrl, Z], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.tick();
+        assert_keys(&[], layout.keycodes());
+
+        // the modifiers of the key code itself are repeated
+        layout.event(Press(0, 3));
+        layout.tick();
+        layout.event(Release(0, 3));
+        layout.tick();
+        layout.event(Press(0, 2));
+        layout.tick();
+        assert_keys(&[LShift, A], layout.keycodes());
+    }
+
+    #[test]
+    fn adaptive() {
+        static LAYERS: Layers<NoCustom, 4, 1, 1> = [[[
+            k(A),
+            Adaptive {
+                default: &k(O),
+                rules: &[(A, &k(U)), (E, &m(&[LShift, Kb1]))],
+            },
+            m(&[LShift, E]),
+            k(LShift),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // no previous key code
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[O], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // after A
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[U], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // after Shift+E, modifiers are ignored
+        layout.event(Press(0, 2));
+        layout.event(Release(0, 2));
+        layout.event(Press(0, 3));
+        layout.event(Release(0, 3));
+        layout.event(Press(0, 1));
+        for _ in 0..5 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[LShift, Kb1], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // the previous key code is now Kb1
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[O], layout.keycodes());
+    }
+
+    #[test]
+    fn min_press_ticks() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[k(A), k(B)]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_min_press_ticks(5);
+
+        // too short, ignored
+        layout.event(Press(0, 0));
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        layout.event(Release(0, 0));
+        for _ in 0..10 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+
+        // long enough
+        layout.event(Press(0, 1));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // the events following a short press are kept
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 0));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+    }
+
+    #[test]
+    fn waiting_state() {
+        static LAYERS: Layers<NoCustom, 2, 2, 1> = [[
+            [k(A), k(B)],
+            [
+                k(C),
+                HoldTap {
+                    timeout: 200,
+                    hold: &k(LAlt),
+                    tap: &k(Space),
+                    config: HoldTapConfig::Default,
+                    tap_hold_interval: 0,
+                    on_timeout: None,
+                },
+            ],
+        ]];
+        let mut layout = Layout::new(&LAYERS);
+        assert_eq!(None, layout.waiting_coord());
+        layout.event(Press(1, 1));
+        assert_eq!(None, layout.waiting_coord());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(Some((1, 1)), layout.waiting_coord());
+        assert_eq!(Some((&k(Space), &k(LAlt))), layout.waiting_actions());
+        layout.event(Release(1, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(None, layout.waiting_coord());
+        assert_eq!(None, layout.waiting_actions());
+        assert_keys(&[Space], layout.keycodes());
+    }
+
+    #[test]
+    fn macro_steps() {
+        use crate::action::MacroStep;
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            Macro(&[
+                MacroStep::Press(LCtrl),
+                MacroStep::Tap(C),
+                MacroStep::Press(LShift),
+                MacroStep::Tap(V),
+            ]),
+            k(A),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+
+        // the physical release doesn't interrupt the macro
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, C], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, LShift, A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, LShift, V, A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, LShift, A], layout.keycodes());
+
+        // the remaining keys are released at the end
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn tri_layer() {
+        static LAYERS: Layers<NoCustom, 3, 1, 4> = [
+            [[l(1), l(2), k(A)]],
+            [[Trans, Trans, k(B)]],
+            [[Trans, Trans, k(C)]],
+            [[Trans, Trans, k(D)]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_tri_layer(1, 2, 3);
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(3, layout.current_layer());
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[D], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(2, layout.current_layer());
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[C], layout.keycodes());
+    }
+
+    #[test]
+    fn event_overflow() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LAlt),
+                tap: &k(Space),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(A),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        assert!(!layout.event(Press(0, 0)));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // the hold-tap is pending, the events are stacked
+        for _ in 0..8 {
+            assert!(!layout.event(Press(0, 1)));
+            assert!(!layout.event(Release(0, 1)));
+        }
+        assert_keys(&[], layout.keycodes());
+        // overflow: the hold-tap is forced to hold, and the first
+        // stacked event is processed
+        assert!(layout.event(Press(0, 1)));
+        assert_keys(&[LAlt, A], layout.keycodes());
+    }
+
+    #[test]
+    fn combos() {
+        static LAYERS: Layers<NoCustom, 4, 1, 2> = [
+            [[k(A), k(B), k(C), l(1)]],
+            [[k(Kb1), k(Kb2), k(Kb3), Trans]],
+        ];
+        static COMBOS: [Combo<NoCustom>; 2] = [
+            Combo {
+                keys: &[(0, 0), (0, 1)],
+                action: &k(Escape),
+            },
+            Combo {
+                keys: &[(0, 1), (0, 2)],
+                action: &Trans,
+            },
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_combos(&COMBOS, 30);
+
+        // combo on the default layer
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Escape], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // not pressed in time
+        layout.event(Press(0, 0));
+        for _ in 0..29 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // on a non default layer, the combo is the same
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Escape], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // a transparent combo is resolved on the current layer
+        layout.event(Press(0, 1));
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Kb2], layout.keycodes());
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(0, layout.current_layer());
+    }
+
+    #[test]
+    fn combo_layer() {
+        static LAYERS: Layers<NoCustom, 3, 1, 2> = [[[k(J), k(K), k(A)]], [[Trans, Trans, k(B)]]];
+        static COMBOS: [Combo<NoCustom>; 1] = [Combo {
+            keys: &[(0, 0), (0, 1)],
+            action: &l(1),
+        }];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_combos(&COMBOS, 30);
+
+        // the layer is active while the chord is held
+        for &released in [(0, 1), (0, 0)].iter() {
+            layout.event(Press(0, 0));
+            layout.event(Press(0, 1));
+            layout.tick();
+            layout.tick();
+            assert_eq!(1, layout.current_layer());
+            layout.event(Press(0, 2));
+            layout.tick();
+            assert_keys(&[B], layout.keycodes());
+            layout.event(Release(0, 2));
+            layout.tick();
+
+            // releasing one key of the chord releases the layer
+            layout.event(Release(released.0, released.1));
+            layout.tick();
+            assert_eq!(0, layout.current_layer());
+            assert_keys(&[], layout.keycodes());
+            layout.event(Press(0, 2));
+            layout.tick();
+            assert_keys(&[A], layout.keycodes());
+            layout.event(Release(0, 2));
+            layout.tick();
+            let other = if released == (0, 0) { (0, 1) } else { (0, 0) };
+            layout.event(Release(other.0, other.1));
+            layout.tick();
+            assert_eq!(0, layout.current_layer());
+            assert_keys(&[], layout.keycodes());
+        }
+    }
+
+    #[test]
+    fn combo_with_hold_on_other_key_press() {
+        static LAYERS: Layers<NoCustom, 4, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LCtrl),
+                tap: &k(Space),
+                config: HoldTapConfig::HoldOnOtherKeyPress,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(A),
+            k(B),
+            k(C),
+        ]]];
+        static COMBOS: [Combo<NoCustom>; 1] = [Combo {
+            keys: &[(0, 1), (0, 2)],
+            action: &k(Escape),
+        }];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_combos(&COMBOS, 30);
+
+        // the keys of the combo don't trigger the hold
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 2));
+        for _ in 0..10 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space, Escape], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 2));
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[], layout.keycodes());
+
+        // a combo key pressed alone triggers the hold once the
+        // combo can't be completed
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        for _ in 0..29 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, A], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[], layout.keycodes());
+
+        // another key triggers the hold immediately
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, C], layout.keycodes());
+        layout.event(Release(0, 3));
+        layout.event(Release(0, 0));
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[], layout.keycodes());
+
+        // so does another key during a partial combo: it is not
+        // claimed, the combo can't be completed anymore
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, A, C], layout.keycodes());
+    }
+
+    #[test]
+    fn combo_term() {
+        static LAYERS: Layers<NoCustom, 3, 1, 1> = [[[
+            HoldTap {
+                timeout: 50,
+                hold: &k(LCtrl),
+                tap: &k(Space),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(A),
+            k(B),
+        ]]];
+        static COMBOS: [Combo<NoCustom>; 1] = [Combo {
+            keys: &[(0, 1), (0, 2)],
+            action: &k(Escape),
+        }];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_combos(&COMBOS, 30);
+
+        // second key just within the term
+        layout.event(Press(0, 1));
+        for _ in 0..29 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Escape], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // second key just beyond the term
+        layout.event(Press(0, 1));
+        for _ in 0..29 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 2));
+        for _ in 0..30 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[], layout.keycodes());
+
+        // the term is measured from the press of the first key, even
+        // if its processing is delayed by a waiting hold-tap
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        for _ in 0..35 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        layout.event(Press(0, 2));
+        for _ in 0..14 {
             assert_eq!(CustomEvent::NoEvent, layout.tick());
             assert_keys(&[], layout.keycodes());
         }
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
         assert_keys(&[LCtrl], layout.keycodes());
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LCtrl], layout.keycodes());
+        assert_keys(&[LCtrl, A], layout.keycodes());
+    }
+
+    #[test]
+    fn key_overrides() {
+        static LAYERS: Layers<NoCustom, 3, 1, 1> = [[[k(LShift), k(BSpace), k(LCtrl)]]];
+        static OVERRIDES: [KeyOverride<NoCustom>; 1] = [KeyOverride {
+            trigger: BSpace,
+            with_mods: LShift.as_modifier_bit(),
+            replacement: &k(Delete),
+            suppressed_mods: LShift.as_modifier_bit(),
+        }];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_key_overrides(&OVERRIDES);
+
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_keys(&[BSpace], layout.keycodes());
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 2));
+        layout.tick();
+        layout.tick();
+        assert_keys(&[Delete, LCtrl], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.tick();
+        assert_keys(&[LShift, LCtrl], layout.keycodes());
+    }
+
+    #[test]
+    fn idle() {
+        static LAYERS: Layers<NoCustom, 2, 1, 2> = [[[l(1), k(A)]], [[Trans, k(B)]]];
+        let mut layout = Layout::new(&LAYERS);
+        for _ in 0..1000 {
+            assert!(layout.is_idle());
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+
+        layout.event(Press(0, 0));
+        assert!(!layout.is_idle());
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LCtrl, Space], layout.keycodes());
+        assert!(!layout.is_idle());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LCtrl], layout.keycodes());
+        assert_keys(&[B], layout.keycodes());
         layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert!(!layout.is_idle());
         assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert!(layout.is_idle());
+        assert_eq!(0, layout.current_layer());
         assert_keys(&[], layout.keycodes());
     }
 
     #[test]
-    fn hold_tap_interleaved_timeout() {
+    fn tap_after_release() {
         static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
-            HoldTap {
-                timeout: 200,
-                hold: &k(LAlt),
+            TapDanceHold {
+                timeout: 50,
                 tap: &k(Space),
-                config: HoldTapConfig::Default,
-                tap_hold_interval: 0,
+                hold: &k(LAlt),
+                double: &k(Enter),
             },
             HoldTap {
-                timeout: 20,
+                timeout: 200,
                 hold: &k(LCtrl),
-                tap: &k(Enter),
+                tap: &k(A),
                 config: HoldTapConfig::Default,
                 tap_hold_interval: 0,
+                on_timeout: None,
             },
         ]]];
         let mut layout = Layout::new(&LAYERS);
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
+
+        // the tap is resolved by the timeout, long after the release
         layout.event(Press(0, 0));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
-        layout.event(Press(0, 1));
-        for _ in 0..15 {
+        layout.event(Release(0, 0));
+        for _ in 0..49 {
             assert_eq!(CustomEvent::NoEvent, layout.tick());
             assert_keys(&[], layout.keycodes());
         }
-        layout.event(Release(0, 0));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
         assert_keys(&[Space], layout.keycodes());
-        for _ in 0..10 {
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // the second key is resolved after its release, once the
+        // first one is resolved
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 1));
+        for _ in 0..48 {
             assert_eq!(CustomEvent::NoEvent, layout.tick());
-            assert_keys(&[Space], layout.keycodes());
+            assert_keys(&[], layout.keycodes());
         }
-        layout.event(Release(0, 1));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[Space, LCtrl], layout.keycodes());
+        assert_keys(&[LAlt], layout.keycodes());
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LCtrl], layout.keycodes());
+        assert_keys(&[LAlt], layout.keycodes());
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
+        assert_keys(&[LAlt, A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Release(0, 0));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
         assert_keys(&[], layout.keycodes());
+        assert!(layout.is_idle());
     }
 
     #[test]
-    fn hold_on_press() {
-        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
-            HoldTap {
-                timeout: 200,
-                hold: &k(LAlt),
-                tap: &k(Space),
-                config: HoldTapConfig::HoldOnOtherKeyPress,
-                tap_hold_interval: 0,
-            },
-            k(Enter),
-        ]]];
+    fn layer_with_fallback() {
+        static LAYERS: Layers<NoCustom, 4, 1, 3> = [
+            [[
+                LayerWithFallback {
+                    layer: 2,
+                    fallback: 1,
+                },
+                l(2),
+                k(A),
+                k(B),
+            ]],
+            [[Trans, Trans, k(Kb1), Trans]],
+            [[Trans, Trans, Trans, k(Kb2)]],
+        ];
         let mut layout = Layout::new(&LAYERS);
 
-        // Press another key before timeout
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
+        // Trans resolved on the fallback layer, and then on the
+        // default layer
         layout.event(Press(0, 0));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
-        layout.event(Press(0, 1));
+        assert_eq!(2, layout.current_layer());
+        layout.event(Press(0, 2));
+        layout.event(Press(0, 3));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LAlt], layout.keycodes());
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LAlt, Enter], layout.keycodes());
+        assert_keys(&[Kb1, Kb2], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.event(Release(0, 3));
         layout.event(Release(0, 0));
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[Enter], layout.keycodes());
-        layout.event(Release(0, 1));
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
-
-        // Press another key after timeout
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
-        layout.event(Press(0, 0));
-        for _ in 0..200 {
+        for _ in 0..3 {
             assert_eq!(CustomEvent::NoEvent, layout.tick());
-            assert_keys(&[], layout.keycodes());
         }
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LAlt], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+
+        // a plain layer modifier uses the default layer
         layout.event(Press(0, 1));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LAlt, Enter], layout.keycodes());
-        layout.event(Release(0, 0));
+        assert_eq!(2, layout.current_layer());
+        layout.event(Press(0, 2));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[Enter], layout.keycodes());
+        assert_keys(&[A], layout.keycodes());
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Layout<NoCustom, 2, 1, 1>>();
+        assert_send_sync::<Layout<u8, 12, 4, 3, 8>>();
+        assert_send_sync::<Layout<core::sync::atomic::AtomicBool, 2, 1, 1>>();
+    }
+
+    #[test]
+    fn latch_keycode() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[LatchKeyCode(W), k(A)]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // latched until the next tap
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.tick();
+        layout.tick();
+        assert_keys(&[W], layout.keycodes());
+        layout.event(Press(0, 1));
         layout.event(Release(0, 1));
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.tick();
+        assert_keys(&[W, A], layout.keycodes());
+        layout.tick();
+        assert_keys(&[W], layout.keycodes());
+        for _ in 0..1000 {
+            layout.tick();
+        }
+        assert_keys(&[W], layout.keycodes());
+        layout.event(Press(0, 0));
+        layout.tick();
         assert_keys(&[], layout.keycodes());
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        layout.tick();
         assert_keys(&[], layout.keycodes());
+        assert!(layout.is_idle());
     }
 
     #[test]
-    fn permissive_hold() {
-        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[
+    fn chordal_hold() {
+        static LAYERS: Layers<NoCustom, 4, 1, 1> = [[[
             HoldTap {
                 timeout: 200,
-                hold: &k(LAlt),
-                tap: &k(Space),
-                config: HoldTapConfig::PermissiveHold,
+                hold: &k(LCtrl),
+                tap: &k(F),
+                config: HoldTapConfig::ChordalHold,
                 tap_hold_interval: 0,
+                on_timeout: None,
             },
-            k(Enter),
+            k(D),
+            k(J),
+            k(K),
         ]]];
         let mut layout = Layout::new(&LAYERS);
+        layout.set_hand_map(|(_, j)| if j < 2 { Hand::Left } else { Hand::Right });
 
-        // Press and release another key before timeout
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
+        // opposite hand: hold
         layout.event(Press(0, 0));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
-        layout.event(Press(0, 1));
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
-        layout.event(Release(0, 1));
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LAlt], layout.keycodes());
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LAlt, Enter], layout.keycodes());
+        layout.event(Press(0, 2));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LAlt], layout.keycodes());
+        assert_keys(&[LCtrl], layout.keycodes());
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LAlt], layout.keycodes());
+        assert_keys(&[LCtrl, J], layout.keycodes());
+        layout.event(Release(0, 2));
         layout.event(Release(0, 0));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
         assert_eq!(CustomEvent::NoEvent, layout.tick());
         assert_keys(&[], layout.keycodes());
-    }
 
-    #[test]
-    fn multiple_actions() {
-        static LAYERS: Layers<NoCustom, 2, 1, 2> = [
-            [[MultipleActions(&[l(1), k(LShift)]), k(F)]],
-            [[Trans, k(E)]],
-        ];
-        let mut layout = Layout::new(&LAYERS);
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
+        // same hand: tap
         layout.event(Press(0, 0));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LShift], layout.keycodes());
         layout.event(Press(0, 1));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LShift, E], layout.keycodes());
-        layout.event(Release(0, 1));
+        assert_keys(&[F], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[F, D], layout.keycodes());
         layout.event(Release(0, 0));
+        layout.event(Release(0, 1));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[LShift], layout.keycodes());
         assert_eq!(CustomEvent::NoEvent, layout.tick());
         assert_keys(&[], layout.keycodes());
     }
 
     #[test]
-    fn multiple_custom_actions() {
-        static LAYERS: Layers<u8, 1, 1, 1> = [[[MultipleActions(&[
-            Action::Custom(1),
-            Action::Custom(2),
-            Action::Custom(3),
-        ])]]];
+    fn forced_modifiers() {
+        static LAYERS: Layers<NoCustom, 2, 1, 1> = [[[k(A), k(LAlt)]]];
         let mut layout = Layout::new(&LAYERS);
+        layout.set_forced_modifiers(LAlt.as_modifier_bit());
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Press(0, 0));
+        layout.tick();
+        assert_keys(&[A, LAlt], layout.keycodes());
+        let report: KbHidReport = layout.keycodes().collect();
+        assert_eq!(&[4, 0, 4, 0, 0, 0, 0, 0], report.as_bytes());
 
-        // Custom event
+        // not duplicated when pressed
+        layout.event(Press(0, 1));
+        layout.tick();
+        assert_eq!(2, layout.keycodes().count());
+
+        layout.set_forced_modifiers(0);
+        layout.event(Release(0, 1));
+        layout.tick();
+        assert_keys(&[A], layout.keycodes());
+    }
+
+    #[test]
+    fn paused() {
+        static LAYERS: Layers<u8, 4, 1, 2> = [
+            [[l(1), k(A), Custom(42), k(C)]],
+            [[Trans, k(B), Trans, Trans]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
         layout.event(Press(0, 0));
-        assert_eq!(CustomEvent::Press(&1), layout.tick());
-        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 1));
+        layout.event(Press(0, 2));
+        layout.tick();
+        layout.tick();
+        assert_eq!(CustomEvent::Press(&42), layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Press(0, 3));
 
-        // nothing more
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        // everything is flushed on pause
+        assert_eq!(CustomEvent::Release(&42), layout.set_paused(true));
         assert_keys(&[], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+        layout.event(Release(0, 1));
+        layout.event(Press(0, 1));
+        for _ in 0..10 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
 
-        // release custom
-        layout.event(Release(0, 0));
-        assert_eq!(CustomEvent::Release(&1), layout.tick());
+        // new events are processed once resumed
+        assert_eq!(CustomEvent::NoEvent, layout.set_paused(false));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
         assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[C], layout.keycodes());
+        assert_eq!(0, layout.unmatched_releases());
     }
 
     #[test]
-    fn custom() {
-        static LAYERS: Layers<u8, 1, 1, 1> = [[[Action::Custom(42)]]];
+    fn tap_preferred() {
+        static LAYERS: Layers<NoCustom, 3, 1, 1> = [[[
+            HoldTap {
+                timeout: 200,
+                hold: &k(LAlt),
+                tap: &k(Space),
+                config: HoldTapConfig::TapPreferred,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            HoldTap {
+                timeout: 200,
+                hold: &k(LCtrl),
+                tap: &k(Enter),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            k(A),
+        ]]];
         let mut layout = Layout::new(&LAYERS);
-        assert_eq!(CustomEvent::NoEvent, layout.tick());
-        assert_keys(&[], layout.keycodes());
 
-        // Custom event
+        // interleaved keys don't activate the hold action
         layout.event(Press(0, 0));
-        assert_eq!(CustomEvent::Press(&42), layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
         assert_keys(&[], layout.keycodes());
 
-        // nothing more
+        // the timeout activates the hold action
+        layout.event(Press(0, 0));
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Release(0, 0));
         assert_eq!(CustomEvent::NoEvent, layout.tick());
         assert_keys(&[], layout.keycodes());
 
-        // release custom
+        // on overflow, the tap action is activated, unlike `Default`
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        for _ in 0..8 {
+            layout.event(Press(0, 2));
+            layout.event(Release(0, 2));
+        }
+        assert!(layout.event(Press(0, 2)));
+        assert_keys(&[Space, A], layout.keycodes());
+        layout.event(Release(0, 2));
         layout.event(Release(0, 0));
-        assert_eq!(CustomEvent::Release(&42), layout.tick());
+        for _ in 0..20 {
+            layout.tick();
+        }
         assert_keys(&[], layout.keycodes());
+
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        for _ in 0..8 {
+            layout.event(Press(0, 2));
+            layout.event(Release(0, 2));
+        }
+        assert!(layout.event(Press(0, 2)));
+        assert_keys(&[LCtrl, A], layout.keycodes());
     }
 
     #[test]
@@ -15,10 +15,15 @@
 #![no_std]
 #![deny(missing_docs)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 use usb_device::bus::UsbBusAllocator;
 use usb_device::prelude::*;
 
 pub mod action;
+#[cfg(feature = "alloc")]
+pub mod builder;
 pub mod debounce;
 pub mod debounced_matrix;
 pub mod hid;
@@ -26,6 +31,9 @@ pub mod key_code;
 pub mod keyboard;
 pub mod layout;
 pub mod matrix;
+#[cfg(feature = "record")]
+pub mod record;
+pub mod split;
 
 /// A handly shortcut for the keyberon USB class type.
 pub type Class<'a, B, L> = hid::HidClass<'a, B, keyboard::Keyboard<L>>;
@@ -0,0 +1,159 @@
+//! Recording and replay of the events of a layout, for the
+//! integration tests of a firmware. Needs the `record` feature.
+//!
+//! ```
+//! use keyberon::action::k;
+//! use keyberon::key_code::KeyCode::*;
+//! use keyberon::layout::{Event, Layers, Layout, NoCustom};
+//! use keyberon::record::{replay, RecordingLayout};
+//!
+//! static LAYERS: Layers<NoCustom, 1, 1, 1> = [[[k(A)]]];
+//! let mut recording = RecordingLayout::<_, 1, 1, 1, 0, 16>::new(Layout::new(&LAYERS));
+//! recording.event(Event::Press(0, 0));
+//! recording.tick();
+//!
+//! let mut layout = Layout::new(&LAYERS);
+//! replay(recording.log(), &mut layout);
+//! layout.tick();
+//! assert!(layout.keycodes().eq(recording.layout().keycodes()));
+//! ```
+
+use crate::layout::{CustomEvent, Event, Layout};
+use heapless::Vec;
+
+/// A `Layout` logging the registered events with the number of the
+/// tick they occurred at (i.e. the number of ticks before them).
+///
+/// At most `N` events are logged: the next ones are processed but
+/// not logged (see `RecordingLayout::is_full`).
+pub struct RecordingLayout<
+    T: 'static,
+    const C: usize,
+    const R: usize,
+    const L: usize,
+    const H: usize,
+    const N: usize,
+> {
+    layout: Layout<T, C, R, L, H>,
+    ticks: u32,
+    log: Vec<(u32, Event), N>,
+    full: bool,
+}
+
+impl<T, const C: usize, const R: usize, const L: usize, const H: usize, const N: usize>
+    RecordingLayout<T, C, R, L, H, N>
+{
+    /// Starts a recording on the given layout.
+    pub fn new(layout: Layout<T, C, R, L, H>) -> Self {
+        Self {
+            layout,
+            ticks: 0,
+            log: Vec::new(),
+            full: false,
+        }
+    }
+    /// Registers and logs a key event, see `Layout::event`.
+    pub fn event(&mut self, event: Event) -> bool {
+        if self.log.push((self.ticks, event)).is_err() {
+            self.full = true;
+        }
+        self.layout.event(event)
+    }
+    /// A time event, see `Layout::tick`.
+    pub fn tick(&mut self) -> CustomEvent<T> {
+        self.ticks = self.ticks.wrapping_add(1);
+        self.layout.tick()
+    }
+    /// Returns the number of ticks since the start of the recording.
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+    /// Returns the logged events, with the number of the tick they
+    /// occurred at.
+    pub fn log(&self) -> &[(u32, Event)] {
+        &self.log
+    }
+    /// Returns `true` if some events were not logged.
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+    /// Returns the recorded layout.
+    pub fn layout(&self) -> &Layout<T, C, R, L, H> {
+        &self.layout
+    }
+    /// Returns the recorded layout, mutably. The events registered
+    /// directly on it are not logged.
+    pub fn layout_mut(&mut self) -> &mut Layout<T, C, R, L, H> {
+        &mut self.layout
+    }
+}
+
+/// Replays a log on a new layout: the events are registered at the
+/// tick they occurred at. The layout is left just after the last
+/// event, before its tick.
+pub fn replay<T, const C: usize, const R: usize, const L: usize, const H: usize>(
+    log: &[(u32, Event)],
+    layout: &mut Layout<T, C, R, L, H>,
+) {
+    let mut ticks = 0;
+    for &(tick, event) in log {
+        while ticks < tick {
+            layout.tick();
+            ticks += 1;
+        }
+        layout.event(event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::action::{k, l, Action, HoldTapConfig};
+    use crate::key_code::KeyCode::*;
+    use crate::layout::{Layers, NoCustom};
+
+    static LAYERS: Layers<NoCustom, 3, 1, 2> = [
+        [[
+            Action::HoldTap {
+                timeout: 20,
+                hold: &k(LCtrl),
+                tap: &k(Space),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                on_timeout: None,
+            },
+            l(1),
+            k(A),
+        ]],
+        [[Action::Trans, Action::Trans, k(B)]],
+    ];
+
+    #[test]
+    fn record_and_replay() {
+        let mut recording = RecordingLayout::<_, 3, 1, 2, 0, 8>::new(Layout::new(&LAYERS));
+        recording.event(Event::Press(0, 0));
+        for _ in 0..30 {
+            recording.tick();
+        }
+        recording.event(Event::Press(0, 1));
+        recording.tick();
+        recording.event(Event::Press(0, 2));
+        recording.event(Event::Release(0, 0));
+        for _ in 0..3 {
+            recording.tick();
+        }
+        assert_eq!(4, recording.log().len());
+        assert_eq!((31, Event::Press(0, 2)), recording.log()[2]);
+        assert!(!recording.is_full());
+
+        let mut layout = Layout::new(&LAYERS);
+        replay(recording.log(), &mut layout);
+        for _ in 31..recording.ticks() {
+            layout.tick();
+        }
+        assert!(layout.keycodes().eq(recording.layout().keycodes()));
+        assert!(layout.keycodes().eq([B].iter().copied()));
+        assert_eq!(1, layout.current_layer());
+        assert_eq!(recording.layout().current_layer(), layout.current_layer());
+    }
+}
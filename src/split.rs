@@ -0,0 +1,360 @@
+//! Framing of the events sent between the halves of a split keyboard,
+//! reordering of these events, and remapping of their coordinates.
+//!
+//! A frame is made of the number of events, then 3 bytes per event
+//! (`b'P'` or `b'R'` followed by the coordinates), and finally a
+//! CRC-8 of all the previous bytes. A frame that is truncated or
+//! corrupted is rejected by [`decode_event_frame`] instead of
+//! producing bogus events.
+//!
+//! ```
+//! use keyberon::layout::Event;
+//! use keyberon::split::{decode_event_frame, event_frame};
+//!
+//! let events = [Event::Press(0, 3), Event::Release(1, 2)];
+//! let frame = event_frame::<8>(&events).unwrap();
+//! let decoded = decode_event_frame::<4>(&frame).unwrap();
+//! assert_eq!(&events[..], &decoded[..]);
+//! ```
+
+use crate::layout::{Event, UNMAPPED_COORD};
+use core::convert::TryFrom;
+use heapless::Vec;
+
+/// The error returned when building or decoding a frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame is shorter or longer than announced by its header.
+    BadLength,
+    /// The checksum of the frame doesn't match its content.
+    BadChecksum,
+    /// An event of the frame is neither a press nor a release.
+    BadEvent,
+    /// The output buffer is too small.
+    Overflow,
+}
+
+/// Builds a frame containing `events`. `N` must be at least `3 *
+/// events.len() + 2`, else `FrameError::Overflow` is returned.
+pub fn event_frame<const N: usize>(events: &[Event]) -> Result<Vec<u8, N>, FrameError> {
+    let len = u8::try_from(events.len()).map_err(|_| FrameError::Overflow)?;
+    let mut frame = Vec::new();
+    frame.push(len).map_err(|_| FrameError::Overflow)?;
+    for e in events {
+        let (i, j) = e.coord();
+        let tag = if e.is_press() { b'P' } else { b'R' };
+        frame
+            .extend_from_slice(&[tag, i, j])
+            .map_err(|_| FrameError::Overflow)?;
+    }
+    frame.push(crc8(&frame)).map_err(|_| FrameError::Overflow)?;
+    Ok(frame)
+}
+
+/// Decodes a frame built by [`event_frame`]. `N` is the maximum
+/// number of events of the frame, else `FrameError::Overflow` is
+/// returned.
+pub fn decode_event_frame<const N: usize>(frame: &[u8]) -> Result<Vec<Event, N>, FrameError> {
+    let (&crc, content) = frame.split_last().ok_or(FrameError::BadLength)?;
+    let (&len, data) = content.split_first().ok_or(FrameError::BadLength)?;
+    if data.len() != 3 * len as usize {
+        return Err(FrameError::BadLength);
+    }
+    if crc8(content) != crc {
+        return Err(FrameError::BadChecksum);
+    }
+    let mut events = Vec::new();
+    for e in data.chunks(3) {
+        let e = match *e {
+            [b'P', i, j] => Event::Press(i, j),
+            [b'R', i, j] => Event::Release(i, j),
+            _ => return Err(FrameError::BadEvent),
+        };
+        events.push(e).map_err(|_| FrameError::Overflow)?;
+    }
+    Ok(events)
+}
+
+/// Reorders and deduplicates the events received from a split half
+/// over an unreliable link, before feeding them to the layout.
+///
+/// Each event is sent with a sequence number, incremented (and
+/// wrapping) by the sending half for each event, starting at 0. The
+/// events are then yielded in the order of their sequence numbers,
+/// and each sequence number only once: a duplicated event, or an
+/// event older than the last yielded one, is dropped.
+///
+/// At most `N` events are kept while waiting for a missing one. When
+/// they are `N`, the missing events are considered lost, and the
+/// filter skips to the oldest kept event. The available events
+/// should thus be popped after each push.
+///
+/// ```
+/// use keyberon::layout::Event;
+/// use keyberon::split::SplitEventFilter;
+///
+/// let mut filter = SplitEventFilter::<4>::new();
+/// filter.push(1, Event::Release(0, 3));
+/// assert_eq!(None, filter.pop());
+/// filter.push(0, Event::Press(0, 3));
+/// assert_eq!(Some(Event::Press(0, 3)), filter.pop());
+/// assert_eq!(Some(Event::Release(0, 3)), filter.pop());
+/// assert_eq!(None, filter.pop());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SplitEventFilter<const N: usize> {
+    next: u8,
+    pending: Vec<(u8, Event), N>,
+}
+
+impl<const N: usize> Default for SplitEventFilter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SplitEventFilter<N> {
+    /// Creates a filter expecting the sequence number 0.
+    pub const fn new() -> Self {
+        Self {
+            next: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Registers an event received with its sequence number. Returns
+    /// `false` if it is dropped, as a duplicate or a stale event, or
+    /// because the available events were not popped.
+    pub fn push(&mut self, seq: u8, event: Event) -> bool {
+        // half of the sequence numbers are in the past
+        if seq.wrapping_sub(self.next) >= 128 || self.pending.iter().any(|&(s, _)| s == seq) {
+            return false;
+        }
+        if self.pending.push((seq, event)).is_err() {
+            return false;
+        }
+        if self.pending.is_full() {
+            self.skip_to_oldest();
+        }
+        true
+    }
+
+    /// Returns the next event in order, if it was received.
+    pub fn pop(&mut self) -> Option<Event> {
+        let idx = self.pending.iter().position(|&(s, _)| s == self.next)?;
+        self.next = self.next.wrapping_add(1);
+        Some(self.pending.swap_remove(idx).1)
+    }
+
+    /// Gives up on the missing events, up to the oldest kept event.
+    fn skip_to_oldest(&mut self) {
+        let next = self.next;
+        if let Some(&(seq, _)) = self
+            .pending
+            .iter()
+            .min_by_key(|&&(s, _)| s.wrapping_sub(next))
+        {
+            self.next = seq;
+        }
+    }
+}
+
+/// Remaps the coordinates of the events with a lookup table, for a
+/// split keyboard whose physical matrices don't map regularly to the
+/// logical grid of the layout.
+///
+/// The table gives the logical coordinates of each physical key:
+/// `table[i][j]` for the key at `(i, j)`. The keys outside of the
+/// table are remapped to `UNMAPPED_COORD`, ignored by the layout.
+/// `UNMAPPED_COORD` can also be used in the table for a hole of the
+/// matrix.
+///
+/// ```
+/// use keyberon::layout::Event;
+/// use keyberon::split::EventRemapper;
+///
+/// static TABLE: [[(u8, u8); 2]; 1] = [[(0, 5), (1, 4)]];
+/// let remapper = EventRemapper::new(&TABLE);
+/// assert_eq!(Event::Press(1, 4), remapper.remap(Event::Press(0, 1)));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct EventRemapper<const C: usize, const R: usize> {
+    table: &'static [[(u8, u8); C]; R],
+}
+
+impl<const C: usize, const R: usize> EventRemapper<C, R> {
+    /// Creates a remapper from its table.
+    pub const fn new(table: &'static [[(u8, u8); C]; R]) -> Self {
+        Self { table }
+    }
+
+    /// Returns the logical coordinates of the physical key `(i, j)`.
+    pub fn coord(&self, (i, j): (u8, u8)) -> (u8, u8) {
+        match self.table.get(i as usize).and_then(|r| r.get(j as usize)) {
+            Some(&coord) => coord,
+            None => UNMAPPED_COORD,
+        }
+    }
+
+    /// Remaps the coordinates of an event.
+    pub fn remap(&self, event: Event) -> Event {
+        event.transform(|i, j| self.coord((i, j)))
+    }
+}
+
+/// CRC-8 with the polynomial 0x07 (CRC-8/SMBUS).
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &b in data {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valid_frame() {
+        let events = [Event::Press(0, 3), Event::Release(1, 2)];
+        let frame = event_frame::<8>(&events).unwrap();
+        assert_eq!(&[2, b'P', 0, 3, b'R', 1, 2], &frame[..7]);
+        assert_eq!(&events[..], &decode_event_frame::<2>(&frame).unwrap()[..]);
+
+        assert!(decode_event_frame::<0>(&event_frame::<2>(&[]).unwrap())
+            .unwrap()
+            .is_empty());
+        assert_eq!(Err(FrameError::Overflow), event_frame::<7>(&events));
+        assert_eq!(Err(FrameError::Overflow), decode_event_frame::<1>(&frame));
+        assert_eq!(0xf4, crc8(b"123456789"));
+    }
+
+    #[test]
+    fn corrupted_frame() {
+        let events = [Event::Press(0, 3), Event::Release(1, 2)];
+        let frame = event_frame::<8>(&events).unwrap();
+
+        let mut corrupted = frame.clone();
+        corrupted[3] ^= 0x10;
+        assert_eq!(
+            Err(FrameError::BadChecksum),
+            decode_event_frame::<2>(&corrupted)
+        );
+
+        let mut corrupted = frame.clone();
+        corrupted[1] = b'X';
+        let crc = crc8(&corrupted[..7]);
+        corrupted[7] = crc;
+        assert_eq!(
+            Err(FrameError::BadEvent),
+            decode_event_frame::<2>(&corrupted)
+        );
+
+        assert_eq!(
+            Err(FrameError::BadLength),
+            decode_event_frame::<2>(&frame[..5])
+        );
+        assert_eq!(Err(FrameError::BadLength), decode_event_frame::<2>(&[]));
+        assert_eq!(Err(FrameError::BadLength), decode_event_frame::<2>(&[0]));
+    }
+
+    #[test]
+    fn filter_duplicate() {
+        let mut filter = SplitEventFilter::<4>::new();
+        assert!(filter.push(0, Event::Press(0, 1)));
+        assert!(!filter.push(0, Event::Press(0, 1)));
+        assert_eq!(Some(Event::Press(0, 1)), filter.pop());
+        assert_eq!(None, filter.pop());
+
+        // a late duplicate, after the event was yielded
+        assert!(!filter.push(0, Event::Press(0, 1)));
+        assert!(filter.push(1, Event::Release(0, 1)));
+        assert_eq!(Some(Event::Release(0, 1)), filter.pop());
+        assert!(!filter.push(1, Event::Release(0, 1)));
+        assert_eq!(None, filter.pop());
+    }
+
+    #[test]
+    fn filter_out_of_order() {
+        let mut filter = SplitEventFilter::<4>::new();
+        assert!(filter.push(1, Event::Release(0, 1)));
+        assert!(filter.push(2, Event::Press(1, 2)));
+        assert_eq!(None, filter.pop());
+        assert!(filter.push(0, Event::Press(0, 1)));
+        assert_eq!(Some(Event::Press(0, 1)), filter.pop());
+        assert_eq!(Some(Event::Release(0, 1)), filter.pop());
+        assert_eq!(Some(Event::Press(1, 2)), filter.pop());
+        assert_eq!(None, filter.pop());
+
+        // wrapping sequence numbers
+        let mut filter = SplitEventFilter::<4>::new();
+        for seq in 0..255 {
+            assert!(filter.push(seq, Event::Press(0, 0)));
+            assert!(filter.pop().is_some());
+        }
+        assert!(filter.push(0, Event::Press(0, 2)));
+        assert!(filter.push(255, Event::Press(0, 1)));
+        assert_eq!(Some(Event::Press(0, 1)), filter.pop());
+        assert_eq!(Some(Event::Press(0, 2)), filter.pop());
+        assert!(!filter.push(255, Event::Press(0, 1)));
+
+        // a lost event, skipped once the buffer is full
+        let mut filter = SplitEventFilter::<2>::new();
+        assert!(filter.push(2, Event::Press(0, 2)));
+        assert_eq!(None, filter.pop());
+        assert!(filter.push(1, Event::Press(0, 1)));
+        assert_eq!(Some(Event::Press(0, 1)), filter.pop());
+        assert_eq!(Some(Event::Press(0, 2)), filter.pop());
+        assert!(filter.push(3, Event::Press(0, 3)));
+        assert_eq!(Some(Event::Press(0, 3)), filter.pop());
+        assert!(!filter.push(0, Event::Press(0, 0)));
+    }
+
+    #[test]
+    fn remapper() {
+        use crate::action::k;
+        use crate::key_code::KeyCode::*;
+        use crate::layout::{Layers, Layout, NoCustom};
+
+        // the right half, a 2x3 matrix with a hole at (1, 0) and its
+        // columns wired in reverse, with an extra thumb key on the
+        // first row
+        static TABLE: [[(u8, u8); 3]; 2] =
+            [[(0, 5), (0, 4), (2, 3)], [UNMAPPED_COORD, (1, 5), (1, 4)]];
+        static LAYERS: Layers<NoCustom, 6, 3, 1> = [[
+            [k(A), k(B), k(C), k(D), k(E), k(F)],
+            [k(G), k(H), k(I), k(J), k(K), k(L)],
+            [k(M), k(N), k(O), k(P), k(Q), k(R)],
+        ]];
+        let remapper = EventRemapper::new(&TABLE);
+        assert_eq!(Event::Press(0, 5), remapper.remap(Event::Press(0, 0)));
+        assert_eq!(Event::Release(2, 3), remapper.remap(Event::Release(0, 2)));
+        assert_eq!(Event::Press(1, 4), remapper.remap(Event::Press(1, 2)));
+        assert_eq!(UNMAPPED_COORD, remapper.coord((1, 0)));
+        assert_eq!(UNMAPPED_COORD, remapper.coord((2, 0)));
+        assert_eq!(UNMAPPED_COORD, remapper.coord((0, 3)));
+
+        let mut layout = Layout::new(&LAYERS);
+        for &coord in [(0, 2), (1, 0), (1, 1), (5, 5)].iter() {
+            layout.event(remapper.remap(Event::press(coord)));
+        }
+        for _ in 0..4 {
+            layout.tick();
+        }
+        let mut keycodes: Vec<_, 4> = layout.keycodes().collect();
+        keycodes.sort_unstable();
+        assert_eq!(&[L, P][..], &keycodes[..]);
+
+        // the releases of the unmapped keys are ignored too
+        layout.event(remapper.remap(Event::release((1, 0))));
+        assert_eq!(0, layout.unmatched_releases());
+    }
+}